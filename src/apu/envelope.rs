@@ -0,0 +1,127 @@
+/*
+Piezas comunes a varios canales
+
+NRx2 - Volume Envelope (canales 1, 2 y 4)
+  Bit 7-4 - Volumen inicial (0-15)
+  Bit 3   - Dirección (0 = baja, 1 = sube)
+  Bit 2-0 - Periodo en pasos de 64 Hz (0 = el volumen no cambia)
+Si los bits 7-3 son 0 el DAC del canal está apagado y el canal se desactiva.
+
+El contador de longitud se decrementa a 256 Hz mientras esté habilitado
+(bit 6 de NRx4) y al llegar a 0 apaga el canal. */
+
+pub struct Envelope {
+    // NRx2
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn get_register(&self) -> u8 {
+        self.register
+    }
+
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// El DAC está encendido si los bits 7-3 de NRx2 no son 0
+    pub fn is_dac_enabled(&self) -> bool {
+        self.register & 0b1111_1000 != 0
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    /// Al disparar el canal se recarga el volumen inicial
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    /// Paso de 64 Hz del frame sequencer
+    pub fn clock(&mut self) {
+        if self.period() == 0 || self.timer == 0 {
+            return;
+        }
+        self.timer -= 1;
+        if self.timer == 0 {
+            self.timer = self.period();
+            if self.register & 0b1000 != 0 {
+                if self.volume < 15 {
+                    self.volume += 1;
+                }
+            } else if self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+pub struct LengthCounter {
+    // 64 para los canales 1, 2 y 4 y 256 para el canal de onda
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Escritura de la longitud en NRx1, el contador cuenta lo que falta
+    pub fn load(&mut self, length: u8) {
+        self.counter = self.max - (length as u16 & (self.max - 1));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Bit 6 de NRx4
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Al disparar el canal con el contador a 0 se carga el máximo
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Paso de 256 Hz del frame sequencer
+    /// Devuelve true si el contador ha llegado a 0 y hay que apagar el canal
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
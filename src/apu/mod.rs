@@ -0,0 +1,394 @@
+/*
+APU: unidad de sonido con cuatro canales
+$FF10-$FF14 - Canal 1: onda cuadrada con sweep
+$FF16-$FF19 - Canal 2: onda cuadrada
+$FF1A-$FF1E - Canal 3: onda de la wave RAM
+$FF20-$FF23 - Canal 4: ruido
+$FF24 - NR50 - Volumen maestro: bits 6-4 izquierda, bits 2-0 derecha (0-7)
+$FF25 - NR51 - Panning: bits 7-4 canales 4-1 a la izquierda, bits 3-0 a la derecha
+$FF26 - NR52 - Bit 7: encendido, bits 3-0: canal 4-1 activo (solo lectura)
+$FF30-$FF3F - Wave RAM
+
+El frame sequencer avanza a 512 Hz con el flanco de bajada del bit 12 del
+divisor del timer (bit 4 de DIV), por eso escribir en DIV también le afecta:
+  Paso:     0   1   2   3   4   5   6   7
+  Longitud  x       x       x       x        (256 Hz)
+  Sweep             x               x        (128 Hz)
+  Envolvente                            x    (64 Hz)
+
+Cada canal da una salida digital de 0 a 15 que su DAC convierte a -1.0..1.0,
+se mezclan según NR51 y NR50, pasan por el filtro paso alto de la salida
+(el condensador que quita la componente continua) y se generan muestras estéreo a la frecuencia
+del host, que se entregan al AudioSink que haya conectado.
+
+Para depurar la música se puede silenciar cada canal, dejar uno solo, o
+grabar cada canal por separado en su propio AudioSink. Las pistas de cada
+canal no se ven afectadas por el silencio y usan la misma escala que la mezcla,
+así sumándolas se obtiene la mezcla completa. */
+
+pub mod envelope;
+pub mod noise;
+pub mod sink;
+pub mod square;
+pub mod wav;
+pub mod wave;
+
+use crate::apu::noise::NoiseChannel;
+use crate::apu::sink::AudioSink;
+use crate::apu::square::SquareChannel;
+use crate::apu::wave::WaveChannel;
+
+/// Ciclos de reloj por segundo de la Game Boy
+pub const CPU_CLOCK: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Canales de sonido, en el orden de sus bits en NR51 y NR52
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+
+    /// Posición del canal (0-3), es también su bit en NR52 y NR51
+    pub fn index(self) -> usize {
+        match self {
+            Channel::Square1 => 0,
+            Channel::Square2 => 1,
+            Channel::Wave => 2,
+            Channel::Noise => 3,
+        }
+    }
+}
+
+pub struct Apu {
+    // NR52 bit 7
+    enabled: bool,
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    nr50: u8,
+    nr51: u8,
+    frame_sequencer_step: u8,
+    // Bit 12 del divisor en el paso anterior
+    last_divider_bit: bool,
+    sample_rate: u32,
+    // Acumulador para repartir las muestras entre los ciclos de reloj
+    sample_clock: u32,
+    // Carga de los condensadores del filtro paso alto (izquierda, derecha)
+    capacitor: (f32, f32),
+    // Destino de las muestras, sin él no se mezcla nada
+    sink: Option<Box<dyn AudioSink>>,
+    // Canales que no suenan en la mezcla
+    muted: [bool; 4],
+    // Canal que suena solo en la mezcla
+    solo: Option<Channel>,
+    // Filtro y destino de la pista de cada canal
+    channel_capacitors: [(f32, f32); 4],
+    channel_sinks: [Option<Box<dyn AudioSink>>; 4],
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Apu {
+    /// sample_rate en Hz, se limita a 1..=CPU_CLOCK (como mucho una muestra por ciclo)
+    pub fn new(sample_rate: u32) -> Apu {
+        Apu {
+            enabled: false,
+            channel1: SquareChannel::new(true),
+            channel2: SquareChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            nr50: 0,
+            nr51: 0,
+            frame_sequencer_step: 0,
+            last_divider_bit: false,
+            sample_rate: sample_rate.clamp(1, CPU_CLOCK),
+            sample_clock: 0,
+            capacitor: (0.0, 0.0),
+            sink: None,
+            muted: [false; 4],
+            solo: None,
+            channel_capacitors: [(0.0, 0.0); 4],
+            channel_sinks: [None, None, None, None],
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Cambia la frecuencia de muestreo, se limita a 1..=CPU_CLOCK Hz
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.clamp(1, CPU_CLOCK);
+        self.sample_clock = 0;
+    }
+
+    /// Conecta el destino de las muestras, sustituye al anterior
+    pub fn set_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.sink = Some(sink);
+    }
+
+    /// Desconecta el destino de las muestras para poder cerrarlo
+    pub fn take_sink(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.sink.take()
+    }
+
+    /// Conecta el destino de la pista de un canal
+    pub fn set_channel_sink(&mut self, channel: Channel, sink: Box<dyn AudioSink>) {
+        self.channel_sinks[channel.index()] = Some(sink);
+    }
+
+    pub fn take_channel_sink(&mut self, channel: Channel) -> Option<Box<dyn AudioSink>> {
+        self.channel_sinks[channel.index()].take()
+    }
+
+    pub fn is_channel_muted(&self, channel: Channel) -> bool {
+        self.muted[channel.index()]
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    pub fn get_solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    /// Deja sonar solo un canal en la mezcla, None vuelve a activar todos
+    pub fn set_solo(&mut self, solo: Option<Channel>) {
+        self.solo = solo;
+    }
+
+    /// El canal suena en la mezcla si no está silenciado y no hay otro en solo
+    pub fn is_channel_audible(&self, channel: Channel) -> bool {
+        !self.is_channel_muted(channel) && (self.solo.is_none() || self.solo == Some(channel))
+    }
+
+    fn has_sinks(&self) -> bool {
+        self.sink.is_some() || self.channel_sinks.iter().any(Option::is_some)
+    }
+
+    /// Lectura de $FF10-$FF3F, los bits que no existen los pone a 1 la MMU
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF10..=0xFF14 => self.channel1.read((address - 0xFF10) as u8),
+            0xFF15..=0xFF19 => self.channel2.read((address - 0xFF15) as u8),
+            0xFF1A..=0xFF1E => self.channel3.read((address - 0xFF1A) as u8),
+            0xFF1F..=0xFF23 => self.channel4.read((address - 0xFF1F) as u8),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                (self.enabled as u8) << 7
+                    | (self.channel4.is_enabled() as u8) << 3
+                    | (self.channel3.is_enabled() as u8) << 2
+                    | (self.channel2.is_enabled() as u8) << 1
+                    | self.channel1.is_enabled() as u8
+            }
+            0xFF30..=0xFF3F => self.channel3.read_wave_ram((address - 0xFF30) as usize),
+            _ => 0xFF,
+        }
+    }
+
+    /// Escritura en $FF10-$FF3F
+    pub fn write(&mut self, address: u16, value: u8) {
+        // Con la APU apagada solo se puede escribir NR52 y la wave RAM
+        if !self.enabled && address != 0xFF26 && !(0xFF30..=0xFF3F).contains(&address) {
+            return;
+        }
+        match address {
+            0xFF10..=0xFF14 => self.channel1.write((address - 0xFF10) as u8, value),
+            0xFF15..=0xFF19 => self.channel2.write((address - 0xFF15) as u8, value),
+            0xFF1A..=0xFF1E => self.channel3.write((address - 0xFF1A) as u8, value),
+            0xFF1F..=0xFF23 => self.channel4.write((address - 0xFF1F) as u8, value),
+            0xFF24 => self.nr50 = value,
+            0xFF25 => self.nr51 = value,
+            0xFF26 => self.set_power(value & 0b1000_0000 != 0),
+            0xFF30..=0xFF3F => self
+                .channel3
+                .write_wave_ram((address - 0xFF30) as usize, value),
+            _ => {}
+        }
+    }
+
+    /// Al apagar la APU se borran todos los registros menos la wave RAM
+    fn set_power(&mut self, enabled: bool) {
+        if self.enabled && !enabled {
+            let mut channel3 = WaveChannel::new();
+            for i in 0..16 {
+                channel3.write_wave_ram(i, self.channel3.read_wave_ram(i));
+            }
+            self.channel1 = SquareChannel::new(true);
+            self.channel2 = SquareChannel::new(false);
+            self.channel3 = channel3;
+            self.channel4 = NoiseChannel::new();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.enabled && enabled {
+            self.frame_sequencer_step = 0;
+        }
+        self.enabled = enabled;
+    }
+
+    /// Avanza la APU los ciclos de reloj que ha tardado la última instrucción
+    /// divider es el divisor interno del timer después de avanzar
+    pub fn step(&mut self, cpu_clocks_passed: usize, divider: u16) {
+        let divider_bit = divider & (1 << 12) != 0;
+        if self.last_divider_bit && !divider_bit && self.enabled {
+            self.clock_frame_sequencer();
+        }
+        self.last_divider_bit = divider_bit;
+
+        let mut remaining = cpu_clocks_passed as u32;
+        while remaining > 0 {
+            // Ciclos hasta la siguiente muestra
+            let until_sample = (CPU_CLOCK - self.sample_clock).div_ceil(self.sample_rate);
+            let cycles = remaining.min(until_sample.max(1));
+
+            if self.enabled {
+                self.channel1.step(cycles);
+                self.channel2.step(cycles);
+                self.channel3.step(cycles);
+                self.channel4.step(cycles);
+            }
+
+            self.sample_clock += cycles * self.sample_rate;
+            if self.sample_clock >= CPU_CLOCK {
+                self.sample_clock -= CPU_CLOCK;
+                if self.has_sinks() {
+                    self.push_sample();
+                }
+            }
+            remaining -= cycles;
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_sequencer_step & 1 == 0 {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if self.frame_sequencer_step == 2 || self.frame_sequencer_step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if self.frame_sequencer_step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    /// Salida de los cuatro canales tras sus DAC (-1.0..1.0)
+    fn channel_outputs(&self) -> [f32; 4] {
+        let dac = |output: u8, enabled: bool| {
+            if enabled {
+                output as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+        [
+            dac(self.channel1.output(), self.channel1.is_dac_enabled()),
+            dac(self.channel2.output(), self.channel2.is_dac_enabled()),
+            dac(self.channel3.output(), self.channel3.is_dac_enabled()),
+            dac(self.channel4.output(), self.channel4.is_dac_enabled()),
+        ]
+    }
+
+    /// Mezcla los canales según NR51 y NR50 y entrega una muestra estéreo
+    /// a la mezcla y a las pistas de cada canal
+    fn push_sample(&mut self) {
+        // El condensador conserva 0.999958 de su carga en cada ciclo de reloj
+        let charge_factor = 0.999_958f32.powf(CPU_CLOCK as f32 / self.sample_rate as f32);
+        // Volumen maestro de 1/8 a 8/8, y cada canal aporta como mucho 1/4
+        let left_volume = (((self.nr50 >> 4) & 0b111) + 1) as f32 / 8.0 / 4.0;
+        let right_volume = ((self.nr50 & 0b111) + 1) as f32 / 8.0 / 4.0;
+        let outputs = self.channel_outputs();
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for &channel in Channel::ALL.iter() {
+            let i = channel.index();
+            let (mut channel_left, mut channel_right) = (0.0, 0.0);
+            if self.enabled {
+                if self.nr51 & (0b0001_0000 << i) != 0 {
+                    channel_left = outputs[i] * left_volume;
+                }
+                if self.nr51 & (0b0000_0001 << i) != 0 {
+                    channel_right = outputs[i] * right_volume;
+                }
+            }
+            if self.is_channel_audible(channel) {
+                left += channel_left;
+                right += channel_right;
+            }
+
+            if let Some(sink) = self.channel_sinks[i].as_mut() {
+                let capacitor = &mut self.channel_capacitors[i];
+                sink.push_sample(
+                    high_pass(channel_left, &mut capacitor.0, charge_factor),
+                    high_pass(channel_right, &mut capacitor.1, charge_factor),
+                );
+            }
+        }
+
+        if let Some(sink) = self.sink.as_mut() {
+            sink.push_sample(
+                high_pass(left, &mut self.capacitor.0, charge_factor),
+                high_pass(right, &mut self.capacitor.1, charge_factor),
+            );
+        }
+    }
+}
+
+/// Filtro paso alto: el condensador se carga con la componente continua y se
+/// resta de la salida, charge_factor es lo que conserva entre dos muestras
+fn high_pass(input: f32, capacitor: &mut f32, charge_factor: f32) -> f32 {
+    let output = input - *capacitor;
+    *capacitor = input - output * charge_factor;
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::sink::BufferSink;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sample_rate_is_clamped() {
+        let mut apu = Apu::new(0);
+        assert_eq!(apu.get_sample_rate(), 1);
+        apu.set_sample_rate(u32::MAX);
+        assert_eq!(apu.get_sample_rate(), CPU_CLOCK);
+        apu.set_sample_rate(0);
+        assert_eq!(apu.get_sample_rate(), 1);
+    }
+
+    #[test]
+    fn one_sample_per_second_at_1_hz() {
+        let sink = Rc::new(RefCell::new(BufferSink::new()));
+        let mut apu = Apu::new(0);
+        apu.set_sink(Box::new(sink.clone()));
+        apu.step(CPU_CLOCK as usize, 0);
+        // Izquierda y derecha
+        assert_eq!(sink.borrow().get_samples().len(), 2);
+    }
+}
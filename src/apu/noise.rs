@@ -0,0 +1,134 @@
+/*
+Canal 4: ruido generado con un LFSR
+NR41 - Bit 5-0: longitud (64 - valor)
+NR42 - Envolvente de volumen
+NR43 - Bit 7-4: Desplazamiento del reloj
+       Bit 3:   Ancho del LFSR (0 = 15 bits, 1 = 7 bits, suena más metálico)
+       Bit 2-0: Divisor (0 -> 8, n -> 16 * n)
+NR44 - Bit 7: Trigger, Bit 6: Length enable
+
+El LFSR avanza cada divisor << desplazamiento ciclos de reloj: hace XOR de
+los dos bits bajos, desplaza a la derecha y pone el resultado en el bit 14
+(y también en el 6 en modo de 7 bits). La salida es el bit 0 negado. */
+
+use crate::apu::envelope::{Envelope, LengthCounter};
+
+pub struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    // NR43
+    polynomial: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NoiseChannel {
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            polynomial: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    /// Lectura de NR40-NR44 (register = 0-4), NR40 no existe
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            2 => self.envelope.get_register(),
+            3 => self.polynomial,
+            4 => (self.length.is_enabled() as u8) << 6,
+            _ => 0,
+        }
+    }
+
+    /// Escritura de NR40-NR44 (register = 0-4)
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            1 => self.length.load(value & 0b0011_1111),
+            2 => {
+                self.envelope.set_register(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.polynomial = value,
+            4 => {
+                self.length.set_enabled(value & 0b0100_0000 != 0);
+                if value & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn period(&self) -> u32 {
+        let divisor = match self.polynomial & 0b111 {
+            0 => 8,
+            code => 16 * code as u32,
+        };
+        divisor << (self.polynomial >> 4)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    /// Avanza el LFSR los ciclos de reloj indicados
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            let xor = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.polynomial & 0b1000 != 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    /// Salida digital del canal (0-15)
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            return 0;
+        }
+        self.envelope.get_volume()
+    }
+
+    /// Paso de 256 Hz del frame sequencer
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Paso de 64 Hz del frame sequencer
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+}
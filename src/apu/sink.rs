@@ -0,0 +1,68 @@
+/*
+Destino de las muestras que genera la APU
+
+La APU mezcla los canales y entrega cada muestra estéreo a un AudioSink, así
+se puede grabar el sonido sin tarjeta de sonido (a un fichero WAV o a memoria)
+y comparar la música de un juego entre versiones del emulador.
+
+Las muestras van de -1.0 a 1.0 y llegan a la frecuencia de la APU. */
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+pub trait AudioSink {
+    /// Recibe una muestra estéreo
+    fn push_sample(&mut self, left: f32, right: f32);
+
+    /// Se llama al terminar de grabar, para cerrar ficheros y similares
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Permite seguir accediendo al sink después de dárselo a la APU
+impl<S: AudioSink> AudioSink for Rc<RefCell<S>> {
+    fn push_sample(&mut self, left: f32, right: f32) {
+        self.borrow_mut().push_sample(left, right);
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.borrow_mut().finish()
+    }
+}
+
+/// Guarda todas las muestras en memoria (izquierda y derecha intercaladas)
+pub struct BufferSink {
+    samples: Vec<f32>,
+}
+
+impl Default for BufferSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BufferSink {
+    pub fn new() -> BufferSink {
+        BufferSink {
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn get_samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Devuelve las muestras guardadas y vacía el buffer
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+impl AudioSink for BufferSink {
+    fn push_sample(&mut self, left: f32, right: f32) {
+        self.samples.push(left);
+        self.samples.push(right);
+    }
+}
@@ -0,0 +1,204 @@
+/*
+Canales 1 y 2: onda cuadrada (el canal 2 no tiene sweep)
+NR10 - Sweep (solo canal 1)
+  Bit 6-4 - Periodo en pasos de 128 Hz (0 = sin sweep)
+  Bit 3   - Dirección (0 = la frecuencia sube, 1 = baja)
+  Bit 2-0 - Desplazamiento
+NRx1 - Bit 7-6: Duty (12.5%, 25%, 50%, 75%), Bit 5-0: longitud (64 - valor)
+NRx2 - Envolvente de volumen
+NRx3 - 8 bits bajos de la frecuencia
+NRx4 - Bit 7: Trigger, Bit 6: Length enable, Bit 2-0: 3 bits altos de la frecuencia
+
+El periodo de cada paso de la onda es (2048 - frecuencia) * 4 ciclos de reloj. */
+
+use crate::apu::envelope::{Envelope, LengthCounter};
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+pub struct SquareChannel {
+    enabled: bool,
+    has_sweep: bool,
+    // NR10
+    sweep_register: u8,
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    shadow_frequency: u16,
+    duty: u8,
+    duty_position: usize,
+    length: LengthCounter,
+    envelope: Envelope,
+    frequency: u16,
+    // Ciclos que faltan para el siguiente paso de la onda
+    timer: u32,
+}
+
+impl SquareChannel {
+    pub fn new(has_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            has_sweep,
+            sweep_register: 0,
+            sweep_enabled: false,
+            sweep_timer: 0,
+            shadow_frequency: 0,
+            duty: 0,
+            duty_position: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    /// Lectura de NRx0-NRx4 (register = 0-4), los bits de solo escritura
+    /// los pone a 1 la MMU
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0 => self.sweep_register,
+            1 => self.duty << 6,
+            2 => self.envelope.get_register(),
+            4 => (self.length.is_enabled() as u8) << 6,
+            _ => 0,
+        }
+    }
+
+    /// Escritura de NRx0-NRx4 (register = 0-4)
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0 if self.has_sweep => self.sweep_register = value & 0b0111_1111,
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0b0011_1111);
+            }
+            2 => {
+                self.envelope.set_register(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((value & 0b111) as u16) << 8);
+                self.length.set_enabled(value & 0b0100_0000 != 0);
+                if value & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.shadow_frequency = self.frequency;
+            self.sweep_timer = self.sweep_period();
+            self.sweep_enabled = self.sweep_period() != 8 || self.sweep_shift() != 0;
+            if self.sweep_shift() != 0 {
+                self.calculate_sweep();
+            }
+        }
+    }
+
+    /// Avanza la onda los ciclos de reloj indicados
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    /// Salida digital del canal (0-15)
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_PATTERNS[self.duty as usize][self.duty_position] * self.envelope.get_volume()
+    }
+
+    /// Paso de 256 Hz del frame sequencer
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Paso de 64 Hz del frame sequencer
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Periodo del sweep, el 0 cuenta como 8
+    fn sweep_period(&self) -> u8 {
+        match (self.sweep_register >> 4) & 0b111 {
+            0 => 8,
+            period => period,
+        }
+    }
+
+    fn sweep_shift(&self) -> u8 {
+        self.sweep_register & 0b111
+    }
+
+    /// Calcula la nueva frecuencia del sweep, si pasa de 2047 apaga el canal
+    fn calculate_sweep(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift();
+        let new_frequency = if self.sweep_register & 0b1000 != 0 {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+        if new_frequency > 2047 {
+            self.enabled = false;
+        }
+        new_frequency
+    }
+
+    /// Paso de 128 Hz del frame sequencer
+    pub fn clock_sweep(&mut self) {
+        if !self.has_sweep {
+            return;
+        }
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer > 0 {
+            return;
+        }
+        self.sweep_timer = self.sweep_period();
+        // Con periodo 0 el temporizador corre pero no cambia la frecuencia
+        if self.sweep_enabled && (self.sweep_register >> 4) & 0b111 != 0 {
+            let new_frequency = self.calculate_sweep();
+            if new_frequency <= 2047 && self.sweep_shift() != 0 {
+                self.frequency = new_frequency;
+                self.shadow_frequency = new_frequency;
+                // Se vuelve a calcular solo para comprobar el desbordamiento
+                self.calculate_sweep();
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+/*
+Fichero WAV PCM de 16 bits estéreo
+
+Cabecera de 44 bytes, todo en little endian:
+  "RIFF", tamaño del fichero - 8, "WAVE"
+  "fmt ", 16, formato 1 (PCM), 2 canales, frecuencia, bytes por segundo,
+          bytes por muestra (4), bits por canal (16)
+  "data", tamaño de los datos, y después las muestras izquierda/derecha
+
+Los tamaños no se conocen hasta terminar, se escriben a 0 y se corrigen
+en finish. */
+
+use crate::apu::sink::AudioSink;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    // Bytes de muestras escritos hasta ahora
+    data_size: u32,
+    // Primer error de escritura, push_sample no puede devolverlo
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Crea el fichero y escribe la cabecera
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+            error: None,
+            finished: false,
+        })
+    }
+
+    fn write_sample(&mut self, left: f32, right: f32) -> io::Result<()> {
+        for sample in [left, right].iter() {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.data_size += (CHANNELS * BITS_PER_SAMPLE / 8) as u32;
+        Ok(())
+    }
+
+    /// Corrige los tamaños de la cabecera
+    fn write_sizes(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn push_sample(&mut self, left: f32, right: f32) {
+        if self.error.is_some() || self.finished {
+            return;
+        }
+        if let Err(e) = self.write_sample(left, right) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Devuelve el primer error de escritura si lo ha habido
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_sizes()
+    }
+}
+
+/// Si no se ha llamado a finish la cabecera se corrige al destruirlo
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if !self.finished && self.error.is_none() {
+            let _ = self.finish();
+        }
+    }
+}
@@ -0,0 +1,141 @@
+/*
+Canal 3: onda definida por el juego en la wave RAM
+NR30 - Bit 7: DAC (0 = apagado)
+NR31 - Longitud (256 - valor)
+NR32 - Bit 6-5: Volumen (0 = silencio, 1 = 100%, 2 = 50%, 3 = 25%)
+NR33 - 8 bits bajos de la frecuencia
+NR34 - Bit 7: Trigger, Bit 6: Length enable, Bit 2-0: 3 bits altos de la frecuencia
+$FF30-$FF3F - Wave RAM: 32 muestras de 4 bits, primero el nibble alto
+
+El periodo de cada muestra es (2048 - frecuencia) * 2 ciclos de reloj. */
+
+use crate::apu::envelope::LengthCounter;
+
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    // NR32 bits 6-5
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    // Muestra actual (0-31)
+    position: usize,
+    // Última muestra leída de la wave RAM
+    sample: u8,
+    wave_ram: [u8; 16],
+}
+
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            wave_ram: [0; 16],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// Lectura de NR30-NR34 (register = 0-4)
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0 => (self.dac_enabled as u8) << 7,
+            2 => self.volume_code << 5,
+            4 => (self.length.is_enabled() as u8) << 6,
+            _ => 0,
+        }
+    }
+
+    /// Escritura de NR30-NR34 (register = 0-4)
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0b1000_0000 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.volume_code = (value >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((value & 0b111) as u16) << 8);
+                self.length.set_enabled(value & 0b0100_0000 != 0);
+                if value & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn read_wave_ram(&self, index: usize) -> u8 {
+        self.wave_ram[index]
+    }
+
+    pub fn write_wave_ram(&mut self, index: usize, value: u8) {
+        self.wave_ram[index] = value;
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    /// Avanza la onda los ciclos de reloj indicados
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+            let byte = self.wave_ram[self.position / 2];
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    /// Salida digital del canal (0-15)
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.volume_code == 0 {
+            return 0;
+        }
+        self.sample >> (self.volume_code - 1)
+    }
+
+    /// Paso de 256 Hz del frame sequencer
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+}
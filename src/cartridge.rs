@@ -0,0 +1,463 @@
+/*
+Cabecera del cartucho ($0100-$014F)
+$0100-$0103 - Punto de entrada, normalmente NOP + JP $0150
+$0104-$0133 - Logo de Nintendo
+$0134-$0143 - Título en mayúsculas ASCII, los cartuchos de CGB usan $013F-$0142
+              como código de fabricante y $0143 como flag CGB
+$0144-$0145 - Código de licencia nuevo (dos caracteres ASCII)
+$0146       - Flag SGB ($03 = soporta funciones de SGB)
+$0147       - Tipo de cartucho (MBC, RAM, batería, RTC...)
+$0148       - Tamaño de ROM: 32 KiB << n
+$0149       - Tamaño de RAM externa
+$014A       - Destino ($00 Japón, $01 resto del mundo)
+$014B       - Código de licencia antiguo ($33 -> usar el nuevo)
+$014C       - Versión de la ROM
+$014D       - Checksum de cabecera: x = x - byte - 1 para $0134-$014C
+$014E-$014F - Checksum global: suma de todos los bytes salvo estos dos (big endian)
+              La consola no lo comprueba, si no coincide solo se avisa */
+
+use crate::mbc::{create_mbc, MBC};
+use std::error::Error;
+use std::fmt;
+
+const HEADER_END: usize = 0x0150;
+
+/// Logo de Nintendo que la boot ROM compara con $0104-$0133
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    // El fichero no llega a contener la cabecera completa
+    TooSmall(usize),
+    // Código de tamaño de ROM desconocido en $0148
+    UnknownRomSize(u8),
+    // Código de tamaño de RAM desconocido en $0149
+    UnknownRamSize(u8),
+    // El fichero es más pequeño de lo que indica la cabecera
+    Truncated { expected: usize, actual: usize },
+    HeaderChecksum { expected: u8, computed: u8 },
+    // Tipo de cartucho ($0147) sin MBC emulado, con su nombre
+    UnsupportedMapper(u8, &'static str),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::TooSmall(size) => write!(
+                f,
+                "el fichero ocupa {} bytes y no contiene una cabecera de cartucho",
+                size
+            ),
+            CartridgeError::UnknownRomSize(code) => {
+                write!(f, "tamaño de ROM desconocido en la cabecera: {:#04X}", code)
+            }
+            CartridgeError::UnknownRamSize(code) => {
+                write!(f, "tamaño de RAM desconocido en la cabecera: {:#04X}", code)
+            }
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "la cabecera indica {} bytes de ROM pero el fichero tiene {}",
+                expected, actual
+            ),
+            CartridgeError::HeaderChecksum { expected, computed } => write!(
+                f,
+                "checksum de cabecera incorrecto: esperado {:#04X}, calculado {:#04X}",
+                expected, computed
+            ),
+            CartridgeError::UnsupportedMapper(cartridge_type, name) => write!(
+                f,
+                "tipo de cartucho {:#04X} ({}) no soportado",
+                cartridge_type, name
+            ),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+/// Soporte de Game Boy Color según el byte $0143
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    // Solo DMG
+    None,
+    // $80: funciona en DMG y usa las funciones de CGB
+    Compatible,
+    // $C0: solo funciona en CGB
+    Only,
+}
+
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    // Tamaños en bytes
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: String,
+    pub japanese: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    // El checksum global coincide con el contenido de la ROM, lo calcula
+    // Cartridge::from_bytes porque la cabecera sola no basta
+    pub global_checksum_ok: bool,
+}
+
+impl CartridgeHeader {
+    /// Lee la cabecera de los primeros $150 bytes de la ROM
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb_support = match rom[0x0143] {
+            0x80 => CgbSupport::Compatible,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+        // En los cartuchos de CGB $0143 es el flag y no forma parte del título.
+        // $013F-$0142 deberían ser el código de fabricante, pero muchos juegos
+        // los usan como título, así que se lee hasta $0142
+        let title_end = match cgb_support {
+            CgbSupport::None => 0x0144,
+            _ => 0x0143,
+        };
+        let title: String = rom[0x0134..title_end]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| byte as char)
+            .filter(|c| c.is_ascii_graphic() || *c == ' ')
+            .collect();
+
+        let old_licensee = rom[0x014B];
+        let licensee = if old_licensee == 0x33 {
+            rom[0x0144..0x0146]
+                .iter()
+                .map(|&byte| byte as char)
+                .collect()
+        } else {
+            format!("{:02X}", old_licensee)
+        };
+
+        let rom_size = match rom[0x0148] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x2_0000,
+            0x05 => 0x1_0000,
+            code => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+
+        Ok(CartridgeHeader {
+            title: title.trim_end().to_string(),
+            cgb_support,
+            sgb_support: rom[0x0146] == 0x03,
+            cartridge_type: rom[0x0147],
+            rom_size,
+            ram_size,
+            licensee,
+            japanese: rom[0x014A] == 0x00,
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: ((rom[0x014E] as u16) << 8) | rom[0x014F] as u16,
+            global_checksum_ok: true,
+        })
+    }
+
+    /// Nombre del tipo de cartucho del byte $0147
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "DESCONOCIDO",
+        }
+    }
+
+    /// Devuelve true si el cartucho tiene pila para conservar la RAM apagado
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Título: {}\n\
+             Tipo: {:#04X} ({})\n\
+             ROM: {} KiB, RAM: {} KiB\n\
+             CGB: {:?}, SGB: {}\n\
+             Licencia: {}, Destino: {}, Versión: {}\n\
+             Checksums: cabecera {:#04X}, global {:#06X}{}",
+            self.title,
+            self.cartridge_type,
+            self.cartridge_type_name(),
+            self.rom_size / 1024,
+            self.ram_size / 1024,
+            self.cgb_support,
+            self.sgb_support,
+            self.licensee,
+            if self.japanese {
+                "Japón"
+            } else {
+                "Resto del mundo"
+            },
+            self.version,
+            self.header_checksum,
+            self.global_checksum,
+            if self.global_checksum_ok {
+                ""
+            } else {
+                " (no coincide)"
+            },
+        )
+    }
+}
+
+/// Calcula el checksum de cabecera igual que la boot ROM
+pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1))
+}
+
+/// Calcula el checksum global: suma de todos los bytes salvo $014E y $014F
+pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x014E && *i != 0x014F)
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+}
+
+/// Lee la cabecera que empieza en rom[offset..] y comprueba su checksum
+fn parse_checked_header(rom: &[u8], offset: usize) -> Result<CartridgeHeader, CartridgeError> {
+    let header = CartridgeHeader::parse(&rom[offset..])?;
+    let computed = compute_header_checksum(&rom[offset..]);
+    if computed != header.header_checksum {
+        return Err(CartridgeError::HeaderChecksum {
+            expected: header.header_checksum,
+            computed,
+        });
+    }
+    Ok(header)
+}
+
+/// Los cartuchos MMM01 arrancan con los últimos 32 KiB de la ROM, así que su
+/// cabecera no está al principio del fichero sino en esa zona.
+/// En los demás cartuchos esa zona son datos normales del último banco, por eso
+/// solo se acepta si tiene el logo, el checksum correcto y un tipo MMM01
+fn mmm01_header(rom: &[u8]) -> Option<CartridgeHeader> {
+    if rom.len() <= 0x8000 {
+        return None;
+    }
+    let offset = rom.len() - 0x8000;
+    if rom[offset + 0x0104..offset + 0x0134] != NINTENDO_LOGO {
+        return None;
+    }
+    let header = parse_checked_header(rom, offset).ok()?;
+    matches!(header.cartridge_type, 0x0B..=0x0D).then_some(header)
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    rom: Vec<u8>,
+    // RAM externa del cartucho
+    ram: Vec<u8>,
+    mbc: Box<dyn MBC>,
+    // Se ha escrito en $A000-$BFFF desde la última consulta
+    ram_written: bool,
+}
+
+impl Cartridge {
+    /// Crea un cartucho a partir del contenido del fichero ROM
+    /// comprobando la cabecera y su checksum, el checksum global solo se anota
+    /// en la cabecera
+    pub fn from_bytes(mut rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        // Si la cabecera del principio no es válida puede ser un MMM01
+        let mut header = match parse_checked_header(&rom, 0) {
+            Ok(header) => header,
+            Err(error) => mmm01_header(&rom).ok_or(error)?,
+        };
+
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::Truncated {
+                expected: header.rom_size,
+                actual: rom.len(),
+            });
+        }
+
+        // Los bytes que sobren tras la ROM declarada no son accesibles
+        rom.truncate(header.rom_size);
+        // Muchas ROM parcheadas o homebrew no lo corrigen y funcionan igual
+        header.global_checksum_ok = compute_global_checksum(&rom) == header.global_checksum;
+
+        let mbc = create_mbc(&header, &rom)?;
+        let ram = vec![0; mbc.internal_ram_size().unwrap_or(header.ram_size)];
+        Ok(Cartridge {
+            header,
+            rom,
+            ram,
+            mbc,
+            ram_written: false,
+        })
+    }
+
+    /// Lectura del área de ROM $0000-$7FFF
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mbc.read_rom(&self.rom, address)
+    }
+
+    /// Escritura en el área de ROM $0000-$7FFF, va a los registros del MBC
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mbc.write_rom(address, value);
+    }
+
+    /// Lectura de la RAM externa $A000-$BFFF
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mbc.read_ram(&self.ram, address)
+    }
+
+    /// Escritura en la RAM externa $A000-$BFFF
+    /// Las escrituras que el MBC ignora no cuentan como cambios de la partida
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        if self.mbc.write_ram(&mut self.ram, address, value) {
+            self.ram_written = true;
+        }
+    }
+
+    /// Devuelve true si el juego ha escrito en la RAM externa o en el reloj
+    /// desde la última llamada y borra el aviso
+    pub fn take_ram_written(&mut self) -> bool {
+        std::mem::replace(&mut self.ram_written, false)
+    }
+
+    /// Devuelve true si el motor de vibración del cartucho está encendido
+    pub fn is_rumbling(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    /// Contenido de la partida: la RAM externa seguida del reloj si el MBC lo tiene
+    pub fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.mbc.save_rtc() {
+            data.extend_from_slice(&rtc);
+        }
+        data
+    }
+
+    /// Restaura una partida guardada con save_data, los datos que sobran
+    /// tras la RAM son el estado del reloj
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+        if data.len() > self.ram.len() {
+            self.mbc.load_rtc(&data[self.ram.len()..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM de 64 KiB con una cabecera válida en offset
+    fn rom_with_header(offset: usize, cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x1_0000];
+        rom[offset + 0x0104..offset + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[offset + 0x0134..offset + 0x0138].copy_from_slice(b"TEST");
+        rom[offset + 0x0147] = cartridge_type;
+        // 64 KiB
+        rom[offset + 0x0148] = 0x01;
+        rom[offset + 0x014D] = compute_header_checksum(&rom[offset..]);
+        rom
+    }
+
+    #[test]
+    fn last_bank_data_is_not_mmm01() {
+        let mut rom = rom_with_header(0, 0x01);
+        for cartridge_type in 0x0B..=0x0D {
+            rom[0x8147] = cartridge_type;
+            let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+            assert_eq!(cartridge.header.cartridge_type, 0x01);
+            assert_eq!(cartridge.header.title, "TEST");
+        }
+    }
+
+    #[test]
+    fn mmm01_header_in_last_32_kib() {
+        let rom = rom_with_header(0x8000, 0x0B);
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert_eq!(cartridge.header.cartridge_type, 0x0B);
+    }
+
+    #[test]
+    fn invalid_header_without_mmm01_fallback() {
+        let mut rom = rom_with_header(0, 0x01);
+        rom[0x014D] ^= 0xFF;
+        rom[0x8147] = 0x0B;
+        assert!(matches!(
+            Cartridge::from_bytes(rom),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn ignored_ram_writes_do_not_mark_save() {
+        let mut rom = rom_with_header(0, 0x03);
+        // 8 KiB de RAM
+        rom[0x0149] = 0x02;
+        rom[0x014D] = compute_header_checksum(&rom);
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+
+        // Con la RAM deshabilitada el MBC ignora la escritura
+        cartridge.write_ram(0xA000, 0x12);
+        assert!(!cartridge.take_ram_written());
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x12);
+        assert!(cartridge.take_ram_written());
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+        assert!(!cartridge.take_ram_written());
+    }
+}
@@ -0,0 +1,67 @@
+/*
+$FF46 - DMA - DMA Transfer and Start Address (R/W)
+Al escribir XX se copian los 160 bytes de $XX00-$XX9F a la OAM ($FE00-$FE9F),
+un byte por ciclo de máquina, así que la copia dura 160 ciclos de máquina
+(más uno de arranque).
+
+Mientras dura la copia el DMA ocupa los buses de memoria y la CPU solo puede
+acceder a HRAM (y a los registros de E/S, que van por el bus interno), por eso
+los juegos ejecutan la rutina que lanza el DMA desde HRAM.
+Las direcciones de origen $E000-$FFFF leen de la echo RAM. */
+
+pub const OAM_DMA_LENGTH: u16 = 160;
+
+pub struct OamDma {
+    // Dirección de origen $XX00
+    source: u16,
+    // Siguiente byte a copiar, None si no hay transferencia en curso
+    index: Option<u16>,
+    // Ciclos de máquina que faltan para que empiece la copia
+    start_delay: u8,
+}
+
+impl Default for OamDma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            source: 0,
+            index: None,
+            start_delay: 0,
+        }
+    }
+
+    /// Escritura en $FF46, reinicia la transferencia si ya había una en curso
+    pub fn start(&mut self, value: u8) {
+        // Por encima de $DF00 el origen es la echo RAM
+        let high = if value >= 0xE0 { value - 0x20 } else { value };
+        self.source = (high as u16) << 8;
+        self.index = Some(0);
+        self.start_delay = 1;
+    }
+
+    /// Devuelve true si el DMA está ocupando los buses
+    pub fn is_active(&self) -> bool {
+        self.index.is_some() && self.start_delay == 0
+    }
+
+    /// Avanza un ciclo de máquina
+    /// Devuelve las direcciones de origen y destino del byte que hay que copiar
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        let index = self.index?;
+        if self.start_delay > 0 {
+            self.start_delay -= 1;
+            return None;
+        }
+        self.index = if index + 1 < OAM_DMA_LENGTH {
+            Some(index + 1)
+        } else {
+            None
+        };
+        Some((self.source + index, 0xFE00 + index))
+    }
+}
@@ -0,0 +1,345 @@
+/*
+PPU con pixel FIFO: el modo 3 se simula punto a punto (1 punto = 1 ciclo de reloj)
+
+El fetcher del fondo repite cuatro pasos: lee el número de tile (2 puntos),
+el byte bajo (2 puntos), el byte alto (2 puntos) y mete los 8 pixels en la
+FIFO del fondo cuando está vacía. Cada punto sale un pixel de la FIFO a la
+pantalla mezclado con el de la FIFO de sprites.
+
+La duración del modo 3 varía (172-289 puntos) según:
+  - SCX % 8: los primeros pixels de la línea se descartan
+  - Sprites: al llegar a la X de un sprite se para la salida de pixels, el
+    fetcher del fondo termina su tile y se leen los datos del sprite (6 puntos)
+  - Ventana: al ir a salir el pixel de WX - 7 se vacía la FIFO y el fetcher
+    vuelve a empezar con el mapa de la ventana (6 puntos). Con WX < 7 los
+    primeros 7 - WX pixels de la ventana quedan fuera de la pantalla
+El HBLANK dura lo que le falte a la línea para llegar a 456 puntos. */
+
+use crate::mmu::MMU;
+use crate::ppu::{apply_palette, tile_data_address, tile_pixel, SCREEN_WIDTH};
+use crate::sprite::{sprites_on_line, Sprite};
+use std::collections::VecDeque;
+
+// Al empezar el modo 3 el fetcher lee un tile que se descarta
+const START_DELAY: usize = 5;
+// Puntos que tarda el fetcher en leer los datos de un sprite
+const SPRITE_FETCH_DOTS: usize = 6;
+
+/// Pixel de la FIFO de sprites
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    palette_1: bool,
+    behind_bg: bool,
+}
+
+pub struct PixelFifo {
+    ly: u8,
+    // Puntos que lleva el modo 3 en esta línea
+    dots: usize,
+    start_delay: usize,
+    bg_fifo: VecDeque<u8>,
+    obj_fifo: VecDeque<ObjPixel>,
+    // Paso del fetcher del fondo: 0-5 leyendo, 6 esperando para meter en la FIFO
+    fetcher_step: u8,
+    // Columna de tile que está leyendo el fetcher
+    fetcher_x: u8,
+    tile_index: u8,
+    tile_low: u8,
+    tile_high: u8,
+    // Pixels de SCX % 8 (o de la ventana con WX < 7) que quedan por descartar
+    discard: u8,
+    // Columna de la pantalla del siguiente pixel
+    lx: usize,
+    sprites: Vec<Sprite>,
+    sprite_height: u8,
+    // Sprite cuyos datos se están leyendo y puntos que faltan
+    sprite_fetch: Option<(Sprite, usize)>,
+    window_y_triggered: bool,
+    window_line: u8,
+    // El fetcher está leyendo la ventana en lugar del fondo
+    in_window: bool,
+    // Colores ya con la paleta aplicada (0-3) de los pixels de la línea
+    line: [u8; SCREEN_WIDTH],
+}
+
+impl Default for PixelFifo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            ly: 0,
+            dots: 0,
+            start_delay: START_DELAY,
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+            fetcher_step: 0,
+            fetcher_x: 0,
+            tile_index: 0,
+            tile_low: 0,
+            tile_high: 0,
+            discard: 0,
+            lx: 0,
+            sprites: Vec::new(),
+            sprite_height: 8,
+            sprite_fetch: None,
+            window_y_triggered: false,
+            window_line: 0,
+            in_window: false,
+            line: [0; SCREEN_WIDTH],
+        }
+    }
+
+    /// Prepara la línea ly al empezar el modo 3
+    pub fn start_line(&mut self, mmu: &MMU, ly: u8, window_y_triggered: bool, window_line: u8) {
+        let lcdc = mmu.peek_byte(0xFF40);
+        self.ly = ly;
+        self.dots = 0;
+        self.start_delay = START_DELAY;
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.fetcher_step = 0;
+        self.fetcher_x = 0;
+        self.discard = mmu.peek_byte(0xFF43) % 8;
+        self.lx = 0;
+        // LCDC bit 2: sprites de 8x16
+        self.sprite_height = if lcdc & 0b0000_0100 != 0 { 16 } else { 8 };
+        // Resultado de la búsqueda en la OAM del modo 2
+        self.sprites = sprites_on_line(mmu, ly, self.sprite_height);
+        self.sprite_fetch = None;
+        self.window_y_triggered = window_y_triggered;
+        self.window_line = window_line;
+        self.in_window = false;
+    }
+
+    /// Devuelve true si ya se han dibujado los 160 pixels de la línea
+    pub fn is_done(&self) -> bool {
+        self.lx >= SCREEN_WIDTH
+    }
+
+    /// Puntos que lleva el modo 3
+    pub fn dots(&self) -> usize {
+        self.dots
+    }
+
+    /// Devuelve true si la ventana ha aparecido en esta línea
+    pub fn used_window(&self) -> bool {
+        self.in_window
+    }
+
+    /// Colores de la línea, ya con las paletas aplicadas
+    pub fn get_line(&self) -> &[u8; SCREEN_WIDTH] {
+        &self.line
+    }
+
+    /// Avanza un punto
+    pub fn tick(&mut self, mmu: &MMU) {
+        self.dots += 1;
+        if self.start_delay > 0 {
+            self.start_delay -= 1;
+            return;
+        }
+        let lcdc = mmu.peek_byte(0xFF40);
+
+        // Lectura de los datos de un sprite, la salida de pixels está parada
+        if let Some((sprite, remaining)) = self.sprite_fetch {
+            if remaining > 1 {
+                self.sprite_fetch = Some((sprite, remaining - 1));
+            } else {
+                self.sprite_fetch = None;
+                self.push_sprite(mmu, &sprite);
+            }
+            return;
+        }
+
+        // LCDC bit 1: sprites activados
+        if lcdc & 0b0000_0010 != 0 && self.discard == 0 {
+            let lx = self.lx as i16;
+            if let Some(position) = self.sprites.iter().position(|sprite| sprite.x <= lx) {
+                // El fetcher del fondo tiene que acabar de leer su tile antes
+                if self.bg_fifo.is_empty() || self.fetcher_step < 5 {
+                    self.step_fetcher(mmu, lcdc);
+                } else {
+                    let sprite = self.sprites.remove(position);
+                    // Este punto ya cuenta como el primero de la lectura
+                    self.sprite_fetch = Some((sprite, SPRITE_FETCH_DOTS - 1));
+                }
+                return;
+            }
+        }
+
+        self.check_window(mmu, lcdc);
+        self.output_pixel(mmu, lcdc);
+        self.step_fetcher(mmu, lcdc);
+    }
+
+    /// Cambia el fetcher a la ventana al ir a salir el pixel de WX - 7
+    fn check_window(&mut self, mmu: &MMU, lcdc: u8) {
+        // LCDC bit 5: ventana activada, bit 0: fondo y ventana activados
+        if self.in_window || !self.window_y_triggered || lcdc & 0b0010_0001 != 0b0010_0001 {
+            return;
+        }
+        // Solo se comprueba cuando hay un pixel para salir, así con WX = 7 el
+        // primer tile del fondo también se lee y se tira
+        if self.bg_fifo.is_empty() {
+            return;
+        }
+        let wx = mmu.peek_byte(0xFF4B);
+        if wx <= 166 && self.lx + 7 >= wx as usize {
+            self.in_window = true;
+            // Con WX < 7 la ventana empieza a la izquierda de la pantalla
+            self.discard = 7u8.saturating_sub(wx);
+            self.bg_fifo.clear();
+            // Este punto ya cuenta como el primero de la lectura del tile
+            self.fetcher_step = 1;
+            self.fetcher_x = 0;
+        }
+    }
+
+    /// Saca un pixel de las FIFO a la pantalla
+    fn output_pixel(&mut self, mmu: &MMU, lcdc: u8) {
+        let bg_color = match self.bg_fifo.pop_front() {
+            Some(color) => color,
+            None => return,
+        };
+        // Los primeros SCX % 8 pixels se descartan
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        // LCDC bit 0: en DMG el fondo apagado se ve en blanco
+        let bg_color = if lcdc & 0b0000_0001 != 0 { bg_color } else { 0 };
+
+        let obj_pixel = self
+            .obj_fifo
+            .pop_front()
+            .filter(|pixel| pixel.color != 0 && lcdc & 0b0000_0010 != 0)
+            .filter(|pixel| !(pixel.behind_bg && bg_color != 0));
+        self.line[self.lx] = match obj_pixel {
+            Some(pixel) => {
+                let obp = mmu.peek_byte(if pixel.palette_1 { 0xFF49 } else { 0xFF48 });
+                apply_palette(obp, pixel.color)
+            }
+            None => apply_palette(mmu.peek_byte(0xFF47), bg_color),
+        };
+        self.lx += 1;
+    }
+
+    /// Avanza un punto el fetcher del fondo o la ventana
+    fn step_fetcher(&mut self, mmu: &MMU, lcdc: u8) {
+        match self.fetcher_step {
+            // Cada lectura tarda 2 puntos, se hace en el segundo
+            1 => {
+                let (tile_map_bit, x, y) = self.fetcher_position(mmu);
+                let tile_map: u16 = if lcdc & tile_map_bit != 0 {
+                    0x9C00
+                } else {
+                    0x9800
+                };
+                self.tile_index = mmu.peek_byte(tile_map + (y as u16 / 8) * 32 + x as u16);
+            }
+            3 => self.tile_low = mmu.peek_byte(self.tile_row_address(mmu, lcdc)),
+            5 => self.tile_high = mmu.peek_byte(self.tile_row_address(mmu, lcdc) + 1),
+            6 => {
+                if !self.bg_fifo.is_empty() {
+                    return;
+                }
+                for bit in (0..8).rev() {
+                    self.bg_fifo
+                        .push_back(tile_pixel(self.tile_low, self.tile_high, bit));
+                }
+                self.fetcher_x = self.fetcher_x.wrapping_add(1);
+                self.fetcher_step = 0;
+                return;
+            }
+            _ => {}
+        }
+        self.fetcher_step += 1;
+    }
+
+    /// Bit de LCDC del mapa de tiles, columna de tile y línea que lee el fetcher
+    fn fetcher_position(&self, mmu: &MMU) -> (u8, u8, u8) {
+        if self.in_window {
+            (0b0100_0000, self.fetcher_x & 31, self.window_line)
+        } else {
+            let scx = mmu.peek_byte(0xFF43);
+            let scy = mmu.peek_byte(0xFF42);
+            (
+                0b0000_1000,
+                (scx / 8).wrapping_add(self.fetcher_x) & 31,
+                self.ly.wrapping_add(scy),
+            )
+        }
+    }
+
+    fn tile_row_address(&self, mmu: &MMU, lcdc: u8) -> u16 {
+        let (_, _, y) = self.fetcher_position(mmu);
+        tile_data_address(lcdc, self.tile_index) + (y as u16 % 8) * 2
+    }
+
+    /// Mezcla los pixels del sprite en la FIFO de sprites
+    /// Los pixels de sprites anteriores tienen prioridad salvo que sean transparentes
+    fn push_sprite(&mut self, mmu: &MMU, sprite: &Sprite) {
+        let address = sprite.row_address(self.ly, self.sprite_height);
+        let low = mmu.peek_byte(address);
+        let high = mmu.peek_byte(address + 1);
+        // Los pixels que quedan a la izquierda de la pantalla no se ven
+        let skip = (self.lx as i16 - sprite.x).max(0) as u8;
+
+        for column in skip..8 {
+            let bit = if sprite.x_flip { column } else { 7 - column };
+            let pixel = ObjPixel {
+                color: tile_pixel(low, high, bit),
+                palette_1: sprite.palette_1,
+                behind_bg: sprite.behind_bg,
+            };
+            let position = (column - skip) as usize;
+            match self.obj_fifo.get_mut(position) {
+                Some(existing) if existing.color == 0 => *existing = pixel,
+                Some(_) => {}
+                None => self.obj_fifo.push_back(pixel),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Duración del modo 3 de la línea 0 con la ventana en wx (None: sin ventana)
+    fn mode3_dots(wx: Option<u8>, scx: u8) -> usize {
+        let mut mmu = MMU::new();
+        mmu.write_byte(0xFF40, if wx.is_some() { 0xB1 } else { 0x91 });
+        mmu.write_byte(0xFF43, scx);
+        mmu.write_byte(0xFF4B, wx.unwrap_or(0));
+        let mut fifo = PixelFifo::new();
+        fifo.start_line(&mmu, 0, wx.is_some(), 0);
+        while !fifo.is_done() {
+            fifo.tick(&mmu);
+        }
+        fifo.dots()
+    }
+
+    #[test]
+    fn mode3_without_window_or_sprites() {
+        assert_eq!(mode3_dots(None, 0), 172);
+        assert_eq!(mode3_dots(None, 3), 175);
+    }
+
+    #[test]
+    fn window_restart_penalty() {
+        assert_eq!(mode3_dots(Some(7), 0), 178);
+        assert_eq!(mode3_dots(Some(87), 0), 178);
+    }
+
+    #[test]
+    fn window_hidden_pixels_with_wx_below_7() {
+        assert_eq!(mode3_dots(Some(0), 0), 185);
+        assert_eq!(mode3_dots(Some(6), 0), 179);
+    }
+}
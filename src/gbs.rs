@@ -0,0 +1,336 @@
+/*
+Ficheros GBS (Game Boy Sound System): el código de música de un juego sin el
+resto del juego, para reproducirlo sin cartucho.
+
+Cabecera ($70 bytes, little endian):
+$00-$02 - "GBS"
+$03     - Versión (1)
+$04     - Número de pistas
+$05     - Primera pista (empieza en 1)
+$06-$07 - Dirección de carga ($0400-$7FFF, igual que init y play)
+$08-$09 - Dirección de la rutina init, recibe la pista (empieza en 0) en A
+$0A-$0B - Dirección de la rutina play
+$0C-$0D - Stack pointer
+$0E     - TMA
+$0F     - TAC, si el bit 2 está activo play se llama con la interrupción del
+          timer, si no con el VBlank
+$10-$2F - Título
+$30-$4F - Autor
+$50-$6F - Copyright
+$70-    - Código y datos, se cargan a partir de la dirección de carga
+
+Para reutilizar la CPU y la MMU se construye un cartucho MBC5 con el código
+en su sitio y un pequeño programa por debajo de $0400:
+$0000-$003F - RST xx -> JP carga + xx
+$0040       - VBlank: CALL play; RETI (si no se usa el timer)
+$0050       - Timer: CALL play; RETI (si se usa el timer)
+$0100       - NOP; JP $0150
+$0150       - Enciende el sonido, configura el timer, llama a init con la
+              pista en A, habilita la interrupción y espera en HALT
+Los cambios de banco se hacen como en el MBC5, escribiendo en $2000-$3FFF. */
+
+use crate::apu::{Apu, CPU_CLOCK};
+use crate::cartridge::{
+    compute_global_checksum, compute_header_checksum, Cartridge, CartridgeError,
+};
+use crate::cpu::CPU;
+use crate::mmu::MMU;
+use crate::ppu::PPU;
+use std::error::Error;
+use std::fmt;
+
+const HEADER_SIZE: usize = 0x70;
+// Primera dirección que no usa el programa que llama a init y play
+const MIN_LOAD_ADDRESS: u16 = 0x0400;
+// Fin del área de ROM, por encima están la VRAM y la RAM
+const MAX_CODE_ADDRESS: u16 = 0x7FFF;
+const DRIVER_ADDRESS: u16 = 0x0150;
+// MBC5 con RAM, 8 MiB como mucho
+const CARTRIDGE_TYPE: u8 = 0x1A;
+const MAX_ROM_SIZE: usize = 0x80_0000;
+
+// Instrucciones del programa que se monta alrededor del código
+const OP_NOP: u8 = 0x00;
+const OP_JP: u8 = 0xC3;
+const OP_CALL: u8 = 0xCD;
+const OP_RETI: u8 = 0xD9;
+
+#[derive(Debug)]
+pub enum GbsError {
+    // El fichero no llega a contener la cabecera
+    TooSmall(usize),
+    // No empieza por "GBS"
+    BadMagic,
+    UnsupportedVersion(u8),
+    // El código se cargaría encima del programa que lo llama o fuera de la ROM
+    InvalidLoadAddress(u16),
+    // init o play fuera de la ROM
+    InvalidRoutineAddress { routine: &'static str, address: u16 },
+    // El código no cabe en un cartucho MBC5
+    TooLarge(usize),
+    // Pista fuera de 1..=número de pistas
+    InvalidTrack { track: u8, count: u8 },
+    Cartridge(CartridgeError),
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GbsError::TooSmall(size) => write!(
+                f,
+                "el fichero ocupa {} bytes y no contiene una cabecera GBS",
+                size
+            ),
+            GbsError::BadMagic => write!(f, "el fichero no es un GBS"),
+            GbsError::UnsupportedVersion(version) => {
+                write!(f, "versión de GBS {} no soportada", version)
+            }
+            GbsError::InvalidLoadAddress(address) => write!(
+                f,
+                "dirección de carga {:#06X} no válida, debe estar entre {:#06X} y {:#06X}",
+                address, MIN_LOAD_ADDRESS, MAX_CODE_ADDRESS
+            ),
+            GbsError::InvalidRoutineAddress { routine, address } => write!(
+                f,
+                "dirección de {} {:#06X} no válida, debe estar entre {:#06X} y {:#06X}",
+                routine, address, MIN_LOAD_ADDRESS, MAX_CODE_ADDRESS
+            ),
+            GbsError::TooLarge(size) => {
+                write!(f, "el código ocupa {} bytes y no cabe en la ROM", size)
+            }
+            GbsError::InvalidTrack { track, count } => write!(
+                f,
+                "la pista {} no existe, el fichero tiene {} pistas",
+                track, count
+            ),
+            GbsError::Cartridge(e) => write!(f, "no se pudo montar el cartucho: {}", e),
+        }
+    }
+}
+
+impl Error for GbsError {}
+
+impl From<CartridgeError> for GbsError {
+    fn from(e: CartridgeError) -> Self {
+        GbsError::Cartridge(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    // Empieza en 1
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+/// Lee un campo de texto de 32 bytes terminado en 0
+fn read_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .filter(|c| c.is_ascii_graphic() || *c == ' ')
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    ((bytes[offset + 1] as u16) << 8) | bytes[offset] as u16
+}
+
+impl GbsHeader {
+    /// Lee la cabecera de los primeros $70 bytes del fichero
+    pub fn parse(gbs: &[u8]) -> Result<GbsHeader, GbsError> {
+        if gbs.len() < HEADER_SIZE {
+            return Err(GbsError::TooSmall(gbs.len()));
+        }
+        if &gbs[0x00..0x03] != b"GBS" {
+            return Err(GbsError::BadMagic);
+        }
+        if gbs[0x03] != 1 {
+            return Err(GbsError::UnsupportedVersion(gbs[0x03]));
+        }
+        let load_address = read_u16(gbs, 0x06);
+        if !(MIN_LOAD_ADDRESS..=MAX_CODE_ADDRESS).contains(&load_address) {
+            return Err(GbsError::InvalidLoadAddress(load_address));
+        }
+        let init_address = read_u16(gbs, 0x08);
+        let play_address = read_u16(gbs, 0x0A);
+        for &(routine, address) in [("init", init_address), ("play", play_address)].iter() {
+            if !(MIN_LOAD_ADDRESS..=MAX_CODE_ADDRESS).contains(&address) {
+                return Err(GbsError::InvalidRoutineAddress { routine, address });
+            }
+        }
+
+        Ok(GbsHeader {
+            version: gbs[0x03],
+            song_count: gbs[0x04],
+            first_song: gbs[0x05],
+            load_address,
+            init_address,
+            play_address,
+            stack_pointer: read_u16(gbs, 0x0C),
+            timer_modulo: gbs[0x0E],
+            timer_control: gbs[0x0F],
+            title: read_string(&gbs[0x10..0x30]),
+            author: read_string(&gbs[0x30..0x50]),
+            copyright: read_string(&gbs[0x50..0x70]),
+        })
+    }
+
+    /// play se llama con la interrupción del timer en vez de con el VBlank
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0b100 != 0
+    }
+}
+
+impl fmt::Display for GbsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Título: {}\n\
+             Autor: {}\n\
+             Copyright: {}\n\
+             Pistas: {} (primera {}), play con {}",
+            self.title,
+            self.author,
+            self.copyright,
+            self.song_count,
+            self.first_song,
+            if self.uses_timer() { "timer" } else { "VBlank" },
+        )
+    }
+}
+
+/// Construye la ROM del cartucho con el código del GBS y el programa que
+/// llama a init con la pista (empieza en 0) y después a play
+fn build_rom(header: &GbsHeader, code: &[u8], track_index: u8) -> Result<Vec<u8>, GbsError> {
+    let code_end = header.load_address as usize + code.len();
+    if code_end > MAX_ROM_SIZE {
+        return Err(GbsError::TooLarge(code.len()));
+    }
+    // La ROM tiene que ser de 32 KiB << n
+    let mut rom_size_code = 0;
+    while (0x8000 << rom_size_code) < code_end {
+        rom_size_code += 1;
+    }
+    let mut rom = vec![0xFF; 0x8000 << rom_size_code];
+    rom[header.load_address as usize..code_end].copy_from_slice(code);
+
+    let [play_low, play_high] = header.play_address.to_le_bytes();
+    let call_play = [OP_CALL, play_low, play_high, OP_RETI];
+
+    // Vectores RST
+    for vector in (0x00..0x40).step_by(8) {
+        let [low, high] = header
+            .load_address
+            .wrapping_add(vector as u16)
+            .to_le_bytes();
+        rom[vector..vector + 3].copy_from_slice(&[OP_JP, low, high]);
+    }
+    // Vectores de interrupción
+    for &vector in [0x40, 0x48, 0x50, 0x58, 0x60].iter() {
+        rom[vector] = OP_RETI;
+    }
+    let play_vector = if header.uses_timer() { 0x50 } else { 0x40 };
+    rom[play_vector..play_vector + 4].copy_from_slice(&call_play);
+
+    // Punto de entrada y cabecera
+    let [driver_low, driver_high] = DRIVER_ADDRESS.to_le_bytes();
+    rom[0x0100..0x0104].copy_from_slice(&[OP_NOP, OP_JP, driver_low, driver_high]);
+    for (i, byte) in header.title.bytes().take(15).enumerate() {
+        rom[0x0134 + i] = byte.to_ascii_uppercase();
+    }
+    rom[0x0143] = 0x00;
+    rom[0x0147] = CARTRIDGE_TYPE;
+    rom[0x0148] = rom_size_code as u8;
+    rom[0x0149] = 0x02;
+
+    let [sp_low, sp_high] = header.stack_pointer.to_le_bytes();
+    let [init_low, init_high] = header.init_address.to_le_bytes();
+    let interrupt_enable = if header.uses_timer() { 0x04 } else { 0x01 };
+    #[rustfmt::skip]
+    let driver = [
+        0x3E, 0x0A, 0xEA, 0x00, 0x00,           // LD A,$0A; LD ($0000),A (habilita la RAM)
+        0x3E, 0x80, 0xE0, 0x26,                 // LD A,$80; LDH (NR52),A
+        0x3E, 0xFF, 0xE0, 0x25,                 // LD A,$FF; LDH (NR51),A
+        0x3E, 0x77, 0xE0, 0x24,                 // LD A,$77; LDH (NR50),A
+        0x3E, header.timer_modulo, 0xE0, 0x06,  // LD A,TMA; LDH (TMA),A
+        0x3E, header.timer_control, 0xE0, 0x07, // LD A,TAC; LDH (TAC),A
+        0x3E, 0x80, 0xE0, 0x40,                 // LD A,$80; LDH (LCDC),A (para el VBlank)
+        0x31, sp_low, sp_high,                  // LD SP,stack pointer
+        0x3E, track_index,                      // LD A,pista
+        OP_CALL, init_low, init_high,           // CALL init
+        0x3E, interrupt_enable, 0xE0, 0xFF,     // LD A,IE; LDH (IE),A
+        0xAF, 0xE0, 0x0F,                       // XOR A; LDH (IF),A
+        0xFB,                                   // EI
+        0x76,                                   // HALT
+        0x18, 0xFD,                             // JR -3 (vuelve al HALT)
+    ];
+    let driver_start = DRIVER_ADDRESS as usize;
+    rom[driver_start..driver_start + driver.len()].copy_from_slice(&driver);
+
+    rom[0x014D] = compute_header_checksum(&rom);
+    let [checksum_high, checksum_low] = compute_global_checksum(&rom).to_be_bytes();
+    rom[0x014E] = checksum_high;
+    rom[0x014F] = checksum_low;
+    Ok(rom)
+}
+
+/// Reproduce una pista de un GBS con la CPU, la MMU y la APU del emulador
+pub struct GbsPlayer {
+    pub header: GbsHeader,
+    cpu: CPU,
+    mmu: MMU,
+    ppu: PPU,
+}
+
+impl GbsPlayer {
+    /// Prepara la reproducción de una pista (empieza en 1)
+    pub fn new(gbs: &[u8], track: u8) -> Result<GbsPlayer, GbsError> {
+        let header = GbsHeader::parse(gbs)?;
+        if track == 0 || track > header.song_count {
+            return Err(GbsError::InvalidTrack {
+                track,
+                count: header.song_count,
+            });
+        }
+
+        let rom = build_rom(&header, &gbs[HEADER_SIZE..], track - 1)?;
+        let mut mmu = MMU::new();
+        mmu.load_cartridge(Cartridge::from_bytes(rom)?);
+        let mut cpu = CPU::new();
+        cpu.skip_boot_rom(&mut mmu);
+
+        Ok(GbsPlayer {
+            header,
+            cpu,
+            mmu,
+            ppu: PPU::new(),
+        })
+    }
+
+    /// La APU, para conectar el destino de las muestras
+    pub fn get_apu(&mut self) -> &mut Apu {
+        &mut self.mmu.apu
+    }
+
+    /// Reproduce la pista durante los segundos indicados
+    pub fn run_seconds(&mut self, seconds: u32) {
+        let end = self.cpu.get_cycles() + seconds as usize * CPU_CLOCK as usize;
+        while self.cpu.get_cycles() < end {
+            self.cpu.run_instruction(&mut self.mmu, &mut self.ppu);
+        }
+    }
+}
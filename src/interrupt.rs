@@ -0,0 +1,118 @@
+/*
+$FF0F - IF - Interrupt Flag (R/W)
+$FFFF - IE - Interrupt Enable (R/W)
+  Bit 0: V-Blank  Interrupt  -> INT 40h  (prioridad más alta)
+  Bit 1: LCD STAT Interrupt  -> INT 48h
+  Bit 2: Timer    Interrupt  -> INT 50h
+  Bit 3: Serial   Interrupt  -> INT 58h
+  Bit 4: Joypad   Interrupt  -> INT 60h  (prioridad más baja)
+Los bits 5-7 de IF no existen y se leen siempre a 1. */
+
+/// Fuentes de interrupción, en orden de prioridad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// Todas las interrupciones de mayor a menor prioridad
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Devuelve la máscara del bit de la interrupción en IF e IE
+    pub fn bit_mask(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0b0000_0001,
+            Interrupt::Stat => 0b0000_0010,
+            Interrupt::Timer => 0b0000_0100,
+            Interrupt::Serial => 0b0000_1000,
+            Interrupt::Joypad => 0b0001_0000,
+        }
+    }
+
+    /// Devuelve la dirección a la que salta la CPU al atender la interrupción
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::Stat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+}
+
+/// Registros IF e IE
+#[derive(Debug)]
+pub struct InterruptController {
+    // IF -> interrupciones solicitadas
+    flags: u8,
+    // IE -> interrupciones habilitadas
+    enable: u8,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            flags: 0,
+            enable: 0,
+        }
+    }
+
+    /// Solicita una interrupción activando su bit en IF
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flags |= interrupt.bit_mask();
+    }
+
+    /// Borra la solicitud de una interrupción, lo hace la CPU al atenderla
+    pub fn clear(&mut self, interrupt: Interrupt) {
+        self.flags &= !interrupt.bit_mask();
+    }
+
+    /// Lectura de IF, los bits 5-7 se leen a 1
+    pub fn get_if(&self) -> u8 {
+        self.flags | 0b1110_0000
+    }
+
+    pub fn set_if(&mut self, value: u8) {
+        self.flags = value & 0b0001_1111;
+    }
+
+    pub fn get_ie(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn set_ie(&mut self, value: u8) {
+        self.enable = value;
+    }
+
+    /// Devuelve true si hay alguna interrupción solicitada y habilitada
+    /// (independientemente de IME)
+    pub fn has_pending(&self) -> bool {
+        self.flags & self.enable & 0b0001_1111 != 0
+    }
+
+    /// Devuelve la interrupción pendiente de mayor prioridad
+    pub fn highest_priority(&self) -> Option<Interrupt> {
+        let pending = self.flags & self.enable;
+        Interrupt::ALL
+            .iter()
+            .copied()
+            .find(|interrupt| pending & interrupt.bit_mask() != 0)
+    }
+}
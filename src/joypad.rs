@@ -0,0 +1,112 @@
+/*
+$FF00 - P1/JOYP - Joypad (R/W)
+  Bit 7-6 - No se usan (se leen a 1)
+  Bit 5 - P15 Select Action buttons    (0=Select)
+  Bit 4 - P14 Select Direction buttons (0=Select)
+  Bit 3 - P13 Input: Down  or Start    (0=Pressed) (Read Only)
+  Bit 2 - P12 Input: Up    or Select   (0=Pressed) (Read Only)
+  Bit 1 - P11 Input: Left  or B        (0=Pressed) (Read Only)
+  Bit 0 - P10 Input: Right or A        (0=Pressed) (Read Only)
+
+Los botones forman una matriz de 2x4: el juego selecciona una fila (o las dos)
+con los bits 4-5 y lee las columnas en los bits 0-3. La interrupción de Joypad
+se solicita cuando alguna de las líneas de entrada pasa de 1 a 0. */
+
+use crate::interrupt::{Interrupt, InterruptController};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Bit del botón: direcciones en el nibble bajo, acciones en el alto
+    fn bit_mask(self) -> u8 {
+        match self {
+            Button::Right => 0b0000_0001,
+            Button::Left => 0b0000_0010,
+            Button::Up => 0b0000_0100,
+            Button::Down => 0b0000_1000,
+            Button::A => 0b0001_0000,
+            Button::B => 0b0010_0000,
+            Button::Select => 0b0100_0000,
+            Button::Start => 0b1000_0000,
+        }
+    }
+}
+
+pub struct Joypad {
+    // Bits 4-5 de P1 escritos por el juego
+    select: u8,
+    // Botones pulsados (1 = pulsado), ver Button::bit_mask
+    pressed: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0b0011_0000,
+            pressed: 0,
+        }
+    }
+
+    /// Lectura de P1
+    pub fn get_p1(&self) -> u8 {
+        0b1100_0000 | self.select | self.input_lines()
+    }
+
+    /// Escritura de P1, solo se pueden escribir los bits de selección
+    pub fn set_p1(&mut self, value: u8, interrupts: &mut InterruptController) {
+        let old_lines = self.input_lines();
+        self.select = value & 0b0011_0000;
+        self.check_interrupt(old_lines, interrupts);
+    }
+
+    /// Pulsa o suelta un botón
+    pub fn set_button(
+        &mut self,
+        button: Button,
+        pressed: bool,
+        interrupts: &mut InterruptController,
+    ) {
+        let old_lines = self.input_lines();
+        if pressed {
+            self.pressed |= button.bit_mask();
+        } else {
+            self.pressed &= !button.bit_mask();
+        }
+        self.check_interrupt(old_lines, interrupts);
+    }
+
+    /// Bits 0-3 de P1: a 0 los botones pulsados de las filas seleccionadas
+    fn input_lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & 0b0001_0000 == 0 {
+            lines |= self.pressed & 0b0000_1111;
+        }
+        if self.select & 0b0010_0000 == 0 {
+            lines |= self.pressed >> 4;
+        }
+        !lines & 0b0000_1111
+    }
+
+    fn check_interrupt(&self, old_lines: u8, interrupts: &mut InterruptController) {
+        // Alguna línea ha pasado de 1 a 0
+        if old_lines & !self.input_lines() != 0 {
+            interrupts.request(Interrupt::Joypad);
+        }
+    }
+}
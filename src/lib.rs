@@ -1,4 +1,15 @@
+pub mod apu;
+pub mod cartridge;
 pub mod cpu;
-pub mod instruction;
+pub mod dma;
+pub mod fifo;
+pub mod gbs;
+pub mod interrupt;
+pub mod joypad;
+pub mod mbc;
 pub mod mmu;
 pub mod ppu;
+pub mod save;
+pub mod screenshot;
+pub mod sprite;
+pub mod timer;
@@ -3,56 +3,451 @@
    tetris desensamblado:
    https://github.com/osnr/tetris/blob/master/tetris.asm
 */
+use gbrustemu::apu::wav::WavWriter;
+use gbrustemu::apu::{Channel, CPU_CLOCK};
+use gbrustemu::cartridge::Cartridge;
 use gbrustemu::cpu::CPU;
+use gbrustemu::gbs::{GbsHeader, GbsPlayer};
+use gbrustemu::joypad::Button;
 use gbrustemu::mmu::MMU;
-use gbrustemu::ppu::{LIGHTEST_GREEN, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gbrustemu::ppu::{palette_by_name, FRAME_CYCLES, PALETTES, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gbrustemu::save::SaveFile;
+use gbrustemu::screenshot::save_bmp;
 
-use minifb::{Key, Window, WindowOptions};
-use std::fs::File;
-use std::io::Read;
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use std::env;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use std::time::Instant;
+const USAGE: &str = "\
+Uso: gbrustemu [opciones] ROM
+     gbrustemu --gbs fichero.gbs [--track N] [--seconds S] --out pista.wav
 
-//const WIDTH: usize = 160;
-//const HEIGHT: usize = 144;
-const WIDTH: usize = 256;
-const HEIGHT: usize = 256;
+Opciones:
+  --boot-rom FICHERO        Boot ROM de DMG (256 bytes) en vez de la incluida
+  --scale N                 Escala de la ventana: 1, 2, 4, 8, 16, 32 o fit
+  --palette NOMBRE          Colores: green, gray, pocket o cuatro colores
+                            RRGGBB separados por comas, del más claro al más oscuro
+  --headless                Sin ventana, necesita --frames
+  --frames N                Termina después de N frames
+  --screenshot FICHERO      Guarda la última imagen en un BMP al terminar
+  --trace                   Imprime los registros antes de cada instrucción
+  --mute                    Silencia los cuatro canales en la mezcla
+  --record-audio FICHERO    Graba el sonido en un WAV
+  --record-channels PREFIJO Graba cada canal en PREFIJO_ch1.wav ... PREFIJO_ch4.wav
+  -h, --help                Muestra esta ayuda
 
-fn main() {
+Reproductor de GBS:
+  --gbs FICHERO             Graba una pista de un fichero GBS sin ventana
+  --track N                 Pista, empieza en 1 (por defecto la primera del fichero)
+  --seconds S               Duración en segundos (por defecto 120)
+  --out FICHERO             WAV de salida
+
+Teclas: flechas, X (A), Z (B), Retroceso (Select), Intro (Start), ESC para salir
+        1-4 silencian cada canal, F1-F4 lo dejan solo
+";
+
+/// Tiempo real que dura un frame en la consola
+const FRAME_DURATION: Duration =
+    Duration::from_nanos(FRAME_CYCLES as u64 * 1_000_000_000 / CPU_CLOCK as u64);
+
+/// Teclas del teclado que corresponden a cada botón de la Game Boy
+const KEY_MAP: [(Key, Button); 8] = [
+    (Key::Right, Button::Right),
+    (Key::Left, Button::Left),
+    (Key::Up, Button::Up),
+    (Key::Down, Button::Down),
+    (Key::X, Button::A),
+    (Key::Z, Button::B),
+    (Key::Backspace, Button::Select),
+    (Key::Enter, Button::Start),
+];
+
+/// Teclas para silenciar cada canal de sonido (1-4) o dejarlo solo (F1-F4)
+const CHANNEL_KEYS: [(Key, Key, Channel); 4] = [
+    (Key::Key1, Key::F1, Channel::Square1),
+    (Key::Key2, Key::F2, Channel::Square2),
+    (Key::Key3, Key::F3, Channel::Wave),
+    (Key::Key4, Key::F4, Channel::Noise),
+];
+
+/// Opciones de la línea de comandos
+struct Options {
+    rom_path: Option<String>,
+    boot_rom: Option<String>,
+    scale: Scale,
+    palette: [u32; 4],
+    headless: bool,
+    frames: Option<usize>,
+    screenshot: Option<String>,
+    trace: bool,
+    mute: bool,
+    help: bool,
+    record_audio: Option<String>,
+    record_channels: Option<String>,
+    gbs: Option<String>,
+    track: Option<u8>,
+    seconds: u32,
+    out: Option<String>,
+}
+
+impl Options {
+    /// Lee las opciones, devuelve un mensaje si alguna no es válida
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            rom_path: None,
+            boot_rom: None,
+            scale: Scale::X2,
+            palette: PALETTES[0].1,
+            headless: false,
+            frames: None,
+            screenshot: None,
+            trace: false,
+            mute: false,
+            help: false,
+            record_audio: None,
+            record_channels: None,
+            gbs: None,
+            track: None,
+            seconds: 120,
+            out: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // Las opciones con valor lo toman del argumento siguiente
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Falta el valor de {}", arg))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => options.help = true,
+                "--boot-rom" => options.boot_rom = Some(value()?),
+                "--scale" => options.scale = parse_scale(&value()?)?,
+                "--palette" => options.palette = parse_palette(&value()?)?,
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = Some(parse_number(arg, &value()?)?),
+                "--screenshot" => options.screenshot = Some(value()?),
+                "--trace" => options.trace = true,
+                "--mute" => options.mute = true,
+                "--record-audio" => options.record_audio = Some(value()?),
+                "--record-channels" => options.record_channels = Some(value()?),
+                "--gbs" => options.gbs = Some(value()?),
+                "--track" => options.track = Some(parse_number(arg, &value()?)?),
+                "--seconds" => options.seconds = parse_number(arg, &value()?)?,
+                "--out" => options.out = Some(value()?),
+                _ if arg.starts_with('-') => return Err(format!("Opción desconocida: {}", arg)),
+                _ if options.rom_path.is_none() => options.rom_path = Some(arg.clone()),
+                _ => return Err(format!("Sobra el argumento: {}", arg)),
+            }
+        }
+
+        if options.headless && options.frames.is_none() {
+            return Err("--headless necesita --frames N para saber cuándo terminar".to_string());
+        }
+        if let (Some(_), Some(rom_path)) = (&options.gbs, &options.rom_path) {
+            return Err(format!(
+                "--gbs no usa ROM, sobra el argumento: {}",
+                rom_path
+            ));
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Valor no válido para {}: {}", name, value))
+}
+
+fn parse_scale(value: &str) -> Result<Scale, String> {
+    match value {
+        "1" => Ok(Scale::X1),
+        "2" => Ok(Scale::X2),
+        "4" => Ok(Scale::X4),
+        "8" => Ok(Scale::X8),
+        "16" => Ok(Scale::X16),
+        "32" => Ok(Scale::X32),
+        "fit" => Ok(Scale::FitScreen),
+        _ => Err(format!(
+            "Escala no válida: {} (1, 2, 4, 8, 16, 32 o fit)",
+            value
+        )),
+    }
+}
+
+/// Paleta por nombre o cuatro colores RRGGBB separados por comas
+fn parse_palette(value: &str) -> Result<[u32; 4], String> {
+    if let Some(palette) = palette_by_name(value) {
+        return Ok(palette);
+    }
+    let colors: Vec<u32> = value
+        .split(',')
+        .map(|color| u32::from_str_radix(color.trim_start_matches('#'), 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Paleta no válida: {}", value))?;
+    match colors.as_slice() {
+        &[c0, c1, c2, c3] if colors.iter().all(|&color| color <= 0xFF_FFFF) => Ok([
+            0xFF00_0000 | c0,
+            0xFF00_0000 | c1,
+            0xFF00_0000 | c2,
+            0xFF00_0000 | c3,
+        ]),
+        _ => Err(format!(
+            "Paleta no válida: {} (green, gray, pocket o cuatro colores RRGGBB)",
+            value
+        )),
+    }
+}
+
+/// Muestra el error y termina el programa
+fn fail(message: impl Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Crea un fichero WAV o termina el programa si no se puede
+fn create_wav(path: &str, sample_rate: u32) -> WavWriter<BufWriter<File>> {
+    WavWriter::create(path, sample_rate)
+        .unwrap_or_else(|e| fail(format!("No se pudo crear {}: {}", path, e)))
+}
+
+/// Modo reproductor de GBS: graba una pista en un fichero WAV sin ventana
+/// gbrustemu --gbs fichero.gbs --track N --seconds 120 --out pista.wav
+fn play_gbs(gbs_path: &str, options: &Options) {
+    let gbs =
+        fs::read(gbs_path).unwrap_or_else(|e| fail(format!("No se pudo leer {}: {}", gbs_path, e)));
+    let header = GbsHeader::parse(&gbs).unwrap_or_else(|e| fail(format!("GBS no válido: {}", e)));
+    let track = options.track.unwrap_or(header.first_song);
+    let wav_path = match &options.out {
+        Some(wav_path) => wav_path,
+        None => fail("Falta el fichero de salida: --out pista.wav"),
+    };
+
+    let mut player = GbsPlayer::new(&gbs, track)
+        .unwrap_or_else(|e| fail(format!("No se pudo reproducir {}: {}", gbs_path, e)));
+    println!("{}", player.header);
+    println!(
+        "Grabando la pista {} ({} s) en {}",
+        track, options.seconds, wav_path
+    );
+
+    let sample_rate = player.get_apu().get_sample_rate();
+    let apu = player.get_apu();
+    apu.set_sink(Box::new(create_wav(wav_path, sample_rate)));
+    player.run_seconds(options.seconds);
+    if let Some(mut sink) = player.get_apu().take_sink() {
+        if let Err(e) = sink.finish() {
+            fail(format!("No se pudo grabar el sonido: {}", e));
+        }
+    }
+}
+
+/// El teclado se lee al refrescar la ventana
+fn read_keys(window: &Window, mmu: &mut MMU) {
+    for &(key, button) in KEY_MAP.iter() {
+        mmu.joypad
+            .set_button(button, window.is_key_down(key), &mut mmu.interrupts);
+    }
+
+    for &(mute_key, solo_key, channel) in CHANNEL_KEYS.iter() {
+        if window.is_key_pressed(mute_key, KeyRepeat::No) {
+            let muted = !mmu.apu.is_channel_muted(channel);
+            mmu.apu.set_channel_muted(channel, muted);
+            println!(
+                "{:?} {}",
+                channel,
+                if muted { "silenciado" } else { "activo" }
+            );
+        }
+        if window.is_key_pressed(solo_key, KeyRepeat::No) {
+            let solo = if mmu.apu.get_solo() == Some(channel) {
+                None
+            } else {
+                Some(channel)
+            };
+            mmu.apu.set_solo(solo);
+            println!("Solo: {:?}", solo);
+        }
+    }
+}
+
+fn run_rom(rom_path: &str, options: &Options) {
     // Lee el fichero ROM
-    let mut f = File::open("ROMS/tetris.gb").unwrap();
-    let mut rom_file = Vec::<u8>::new();
-    f.read_to_end(&mut rom_file).unwrap();
+    let rom_file = fs::read(rom_path)
+        .unwrap_or_else(|e| fail(format!("No se pudo leer la ROM {}: {}", rom_path, e)));
+
+    // Comprueba la cabecera antes de arrancar
+    let cartridge =
+        Cartridge::from_bytes(rom_file).unwrap_or_else(|e| fail(format!("ROM no válida: {}", e)));
+    println!("{}", cartridge.header);
+    if !cartridge.header.global_checksum_ok {
+        eprintln!("Aviso: el checksum global no coincide, la ROM puede estar modificada");
+    }
+    let title = format!("{} - ESC para salir", cartridge.header.title);
 
     let mut mmu = MMU::new();
-    // Pone el fichero ROM en la memoria RAM
-    mmu.from_rom_file(&rom_file);
+    if let Some(boot_rom_path) = &options.boot_rom {
+        let boot_rom = fs::read(boot_rom_path).unwrap_or_else(|e| {
+            fail(format!(
+                "No se pudo leer la boot ROM {}: {}",
+                boot_rom_path, e
+            ))
+        });
+        if boot_rom.len() != 256 {
+            fail(format!(
+                "Boot ROM no válida: {} ocupa {} bytes y debe ocupar 256",
+                boot_rom_path,
+                boot_rom.len()
+            ));
+        }
+        let mut bytes = [0; 256];
+        bytes.copy_from_slice(&boot_rom);
+        mmu.set_boot_rom(bytes);
+    }
+    // Inserta el cartucho
+    mmu.load_cartridge(cartridge);
+
+    // Partida guardada de los cartuchos con pila
+    let mut save_file = SaveFile::for_rom(Path::new(rom_path));
+    if let Err(e) = save_file.load(&mut mmu) {
+        eprintln!("No se pudo leer {}: {}", save_file.path().display(), e);
+    }
+
+    // Graba el sonido en un fichero WAV
+    let sample_rate = mmu.apu.get_sample_rate();
+    if let Some(wav_path) = &options.record_audio {
+        mmu.apu
+            .set_sink(Box::new(create_wav(wav_path, sample_rate)));
+    }
+    // Y cada canal en su propio fichero
+    if let Some(prefix) = &options.record_channels {
+        for &channel in Channel::ALL.iter() {
+            let wav_path = format!("{}_ch{}.wav", prefix, channel.index() + 1);
+            mmu.apu
+                .set_channel_sink(channel, Box::new(create_wav(&wav_path, sample_rate)));
+        }
+    }
+    if options.mute {
+        for &channel in Channel::ALL.iter() {
+            mmu.apu.set_channel_muted(channel, true);
+        }
+    }
 
     // Ejecuta instrucciones en RAM
     let mut cpu = CPU::new();
+    if options.trace {
+        cpu.set_debug_flag();
+    }
     let mut ppu = PPU::new();
+    ppu.set_palette(options.palette);
+
+    let mut window = if options.headless {
+        None
+    } else {
+        let window_options = WindowOptions {
+            scale: options.scale,
+            ..WindowOptions::default()
+        };
+        let window = Window::new(&title, SCREEN_WIDTH, SCREEN_HEIGHT, window_options)
+            .unwrap_or_else(|e| fail(format!("No se pudo abrir la ventana: {}", e)));
+        Some(window)
+    };
 
-    let mut screen = vec![LIGHTEST_GREEN; SCREEN_WIDTH * SCREEN_HEIGHT];
-    let mut window = Window::new(
-        "Prueba - ESC para salir",
-        SCREEN_WIDTH,
-        SCREEN_HEIGHT,
-        WindowOptions::default(),
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        //let now = Instant::now();
+    let cycle_limit = options.frames.map(|frames| frames * FRAME_CYCLES);
+    // La ventana y el teclado se atienden cada FRAME_CYCLES de CPU, aunque el
+    // LCD esté apagado y la PPU no termine frames (un STOP esperando un botón)
+    let mut next_poll = FRAME_CYCLES;
+    let mut new_frame = false;
+    let mut next_frame_time = Instant::now() + FRAME_DURATION;
+    while cycle_limit.is_none_or(|limit| cpu.get_cycles() < limit) {
         cpu.run_instruction(&mut mmu, &mut ppu);
+        new_frame |= ppu.take_frame_ready();
+        if cpu.get_cycles() < next_poll {
+            continue;
+        }
+        next_poll += FRAME_CYCLES;
 
-        if ppu.is_lcd_enable(&mmu) {
-            if mmu.dirty_viewport_flag || mmu.dirty_vram_flag {
-                let current_viewport = ppu.get_viewport();
-                window.update_with_buffer(current_viewport).unwrap();
+        if let Some(window) = window.as_mut() {
+            if !window.is_open() || window.is_key_down(Key::Escape) {
+                break;
             }
+            // Solo se redibuja si la PPU ha terminado un frame
+            if new_frame {
+                if let Err(e) = window.update_with_buffer(ppu.get_viewport()) {
+                    fail(format!("No se pudo dibujar la ventana: {}", e));
+                }
+            } else {
+                window.update();
+            }
+            read_keys(window, &mut mmu);
+
+            // Espera para ir al ritmo de la consola (unos 59.7 frames por segundo)
+            let now = Instant::now();
+            if next_frame_time > now {
+                thread::sleep(next_frame_time - now);
+                next_frame_time += FRAME_DURATION;
+            } else {
+                // Si vamos con retraso no se intenta recuperar de golpe
+                next_frame_time = now + FRAME_DURATION;
+            }
+        }
+        new_frame = false;
+
+        if let Err(e) = save_file.update(&mut mmu) {
+            eprintln!("No se pudo guardar {}: {}", save_file.path().display(), e);
+        }
+    }
+
+    if let Some(screenshot_path) = &options.screenshot {
+        if let Err(e) = save_bmp(
+            screenshot_path,
+            ppu.get_viewport(),
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+        ) {
+            eprintln!("No se pudo guardar {}: {}", screenshot_path, e);
         }
-        //let new_now = Instant::now();
-        //println!("{:?}", new_now.duration_since(now));
+    }
+
+    if let Err(e) = save_file.flush_if_dirty(&mut mmu) {
+        eprintln!("No se pudo guardar {}: {}", save_file.path().display(), e);
+    }
+
+    let mut sinks: Vec<_> = mmu.apu.take_sink().into_iter().collect();
+    for &channel in Channel::ALL.iter() {
+        sinks.extend(mmu.apu.take_channel_sink(channel));
+    }
+    for mut sink in sinks {
+        if let Err(e) = sink.finish() {
+            eprintln!("No se pudo grabar el sonido: {}", e);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = Options::parse(&args)
+        .unwrap_or_else(|e| fail(format!("{}\nUsa --help para ver las opciones", e)));
+
+    if options.help {
+        print!("{}", USAGE);
+        return;
+    }
+    if let Some(gbs_path) = &options.gbs {
+        play_gbs(gbs_path, &options);
+        return;
+    }
+    match &options.rom_path {
+        Some(rom_path) => run_rom(rom_path, &options),
+        None => fail(format!("Falta la ROM\n\n{}", USAGE)),
     }
 }
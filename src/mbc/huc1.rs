@@ -0,0 +1,75 @@
+/*
+HuC1 de Hudson (hasta 1 MiB de ROM, 32 KiB de RAM y un LED/receptor infrarrojo)
+$0000-$1FFF - $0E pone $A000-$BFFF en modo infrarrojo, cualquier otro valor
+              en modo RAM. A diferencia del MBC1 la RAM no hay que habilitarla
+$2000-$3FFF - ROM Bank Number (6 bits)
+$4000-$5FFF - RAM Bank Number (2 bits)
+$6000-$7FFF - Sin uso
+
+En modo infrarrojo leer devuelve $C0 (no se recibe luz) y escribir enciende
+o apaga el LED, que aquí no hace nada. */
+
+use crate::mbc::{ram_offset, read_rom_bank, MBC};
+
+pub struct HuC1 {
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl Default for HuC1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuC1 {
+    pub fn new() -> HuC1 {
+        HuC1 {
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl MBC for HuC1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, address),
+            _ => read_rom_bank(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = value & 0b0011_1111,
+            0x4000..=0x5FFF => self.ram_bank = value & 0b11,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.ir_mode {
+            return 0xC0;
+        }
+        match ram_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) => ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ir_mode {
+            return false;
+        }
+        match ram_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) => {
+                ram[offset] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
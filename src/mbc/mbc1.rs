@@ -0,0 +1,203 @@
+/*
+MBC1 (hasta 2 MiB de ROM y 32 KiB de RAM)
+$0000-$1FFF - RAM Enable: $xA habilita la RAM, cualquier otro valor la deshabilita
+$2000-$3FFF - ROM Bank Number: 5 bits bajos del banco de $4000-$7FFF, el valor 0
+              se convierte en 1 (por eso los bancos $20, $40 y $60 no se pueden
+              ver en $4000-$7FFF)
+$4000-$5FFF - RAM Bank Number o bits altos del banco de ROM (2 bits)
+$6000-$7FFF - Banking Mode Select:
+              0 -> el registro de 2 bits solo afecta a $4000-$7FFF
+              1 -> también selecciona el banco de RAM y el banco de $0000-$3FFF
+                   (en ROMs de 1 MiB o más)
+
+Los cartuchos multicart MBC1M conectan el registro de 2 bits a los bits 4-5
+del banco en lugar de a los bits 5-6, cada juego ocupa 16 bancos. */
+
+use crate::mbc::{ram_offset, read_rom_bank, MBC, ROM_BANK_SIZE};
+
+pub struct MBC1 {
+    ram_enable: bool,
+    // Registro de 5 bits
+    bank1: u8,
+    // Registro de 2 bits
+    bank2: u8,
+    mode: bool,
+    // Bits que ocupa bank1 en el número de banco: 5 normalmente, 4 en MBC1M
+    bank1_bits: u8,
+}
+
+impl MBC1 {
+    pub fn new(rom: &[u8]) -> MBC1 {
+        MBC1 {
+            ram_enable: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            bank1_bits: if is_multicart(rom) { 4 } else { 5 },
+        }
+    }
+
+    fn bank1_mask(&self) -> u8 {
+        (1 << self.bank1_bits) - 1
+    }
+
+    /// Banco visible en $0000-$3FFF
+    fn low_rom_bank(&self) -> usize {
+        if self.mode {
+            (self.bank2 as usize) << self.bank1_bits
+        } else {
+            0
+        }
+    }
+
+    /// Banco visible en $4000-$7FFF
+    fn high_rom_bank(&self) -> usize {
+        ((self.bank2 as usize) << self.bank1_bits) | (self.bank1 & self.bank1_mask()) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+/// Los multicart de 1 MiB llevan el logo de Nintendo al principio de cada juego:
+/// se busca una segunda cabecera en el banco $10
+fn is_multicart(rom: &[u8]) -> bool {
+    let second_header = 0x10 * ROM_BANK_SIZE;
+    rom.len() == 64 * ROM_BANK_SIZE
+        && rom[0x0104..0x0134] == rom[second_header + 0x0104..second_header + 0x0134]
+}
+
+impl MBC for MBC1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(rom, self.low_rom_bank(), address),
+            _ => read_rom_bank(rom, self.high_rom_bank(), address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // El 0 se comprueba sobre los 5 bits completos, incluso en MBC1M
+                self.bank1 = value & 0b1_1111;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.bank2 = value & 0b11,
+            _ => self.mode = value & 0b1 != 0,
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match ram_offset(ram, self.ram_bank(), address) {
+            Some(offset) => ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        match ram_offset(ram, self.ram_bank(), address) {
+            Some(offset) => {
+                ram[offset] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::RAM_BANK_SIZE;
+
+    /// ROM en la que el primer byte de cada banco es su número
+    fn numbered_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let rom = numbered_rom(128);
+        let mut mbc = MBC1::new(&rom);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+
+        // Los bancos $20, $40 y $60 se convierten en $21, $41 y $61
+        for bank2 in 1..4 {
+            mbc.write_rom(0x4000, bank2);
+            mbc.write_rom(0x2000, 0x00);
+            assert_eq!(mbc.read_rom(&rom, 0x4000), bank2 * 0x20 + 1);
+        }
+    }
+
+    #[test]
+    fn zero_check_uses_all_5_bits() {
+        let rom = numbered_rom(8);
+        let mut mbc = MBC1::new(&rom);
+        // $08 no es 0 aunque la ROM solo tenga 8 bancos: se ve el banco 0
+        mbc.write_rom(0x2000, 0x08);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0);
+    }
+
+    #[test]
+    fn mode_1_upper_bits() {
+        let rom = numbered_rom(128);
+        let mut mbc = MBC1::new(&rom);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_rom(0x2000, 0x03);
+
+        // Modo 0: $0000-$3FFF siempre es el banco 0
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x43);
+
+        // Modo 1: el registro de 2 bits también elige el banco de $0000-$3FFF
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x40);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x43);
+    }
+
+    #[test]
+    fn mode_1_ram_banking() {
+        let rom = numbered_rom(4);
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = MBC1::new(&rom);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x02);
+
+        // Modo 0: siempre el banco 0 de RAM
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        assert_eq!(ram[0], 0x11);
+
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x22);
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x22);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x22);
+
+        // Con la RAM deshabilitada se lee $FF y no se escribe
+        mbc.write_rom(0x0000, 0x00);
+        mbc.write_ram(&mut ram, 0xA000, 0x33);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x22);
+    }
+}
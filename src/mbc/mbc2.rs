@@ -0,0 +1,75 @@
+/*
+MBC2 (hasta 256 KiB de ROM y 512 x 4 bits de RAM dentro del propio MBC)
+$0000-$3FFF - Registros, el bit 8 de la dirección elige cuál:
+              bit 8 = 0 -> RAM Enable ($xA habilita la RAM)
+              bit 8 = 1 -> ROM Bank Number (4 bits, el 0 se convierte en 1)
+$A000-$A1FF - RAM de 512 nibbles, solo se usan los 4 bits bajos y los altos
+              se leen a 1. El resto de $A000-$BFFF son copias de esta zona. */
+
+use crate::mbc::{read_rom_bank, MBC};
+
+pub const MBC2_RAM_SIZE: usize = 512;
+
+pub struct MBC2 {
+    ram_enable: bool,
+    rom_bank: u8,
+}
+
+impl Default for MBC2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MBC2 {
+    pub fn new() -> MBC2 {
+        MBC2 {
+            ram_enable: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl MBC for MBC2 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, address),
+            _ => read_rom_bank(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        // Solo hay registros en $0000-$3FFF
+        if address >= 0x4000 {
+            return;
+        }
+        if address & 0x0100 == 0 {
+            self.ram_enable = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = value & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enable || ram.is_empty() {
+            return 0xFF;
+        }
+        ram[address as usize % MBC2_RAM_SIZE % ram.len()] | 0xF0
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enable || ram.is_empty() {
+            return false;
+        }
+        let len = ram.len();
+        ram[address as usize % MBC2_RAM_SIZE % len] = value & 0x0F;
+        true
+    }
+
+    fn internal_ram_size(&self) -> Option<usize> {
+        Some(MBC2_RAM_SIZE)
+    }
+}
@@ -0,0 +1,99 @@
+/*
+MBC3 (hasta 2 MiB de ROM, 32 KiB de RAM y reloj de tiempo real opcional)
+$0000-$1FFF - RAM and Timer Enable: $xA habilita la RAM y los registros del reloj
+$2000-$3FFF - ROM Bank Number: 7 bits, el 0 se convierte en 1
+$4000-$5FFF - RAM Bank Number ($00-$03) o registro del reloj ($08-$0C)
+$6000-$7FFF - Latch Clock Data: escribir $00 y luego $01 */
+
+use crate::mbc::rtc::Rtc;
+use crate::mbc::{ram_offset, read_rom_bank, MBC};
+
+pub struct MBC3 {
+    ram_enable: bool,
+    rom_bank: u8,
+    // Banco de RAM o registro del reloj seleccionado en $A000-$BFFF
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+}
+
+impl MBC3 {
+    pub fn new(has_rtc: bool) -> MBC3 {
+        MBC3 {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+        }
+    }
+}
+
+impl MBC for MBC3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, address),
+            _ => read_rom_bank(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0b0111_1111;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match (self.ram_bank, &self.rtc) {
+            (0x00..=0x03, _) => match ram_offset(ram, self.ram_bank as usize, address) {
+                Some(offset) => ram[offset],
+                None => 0xFF,
+            },
+            (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_bank),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        match (self.ram_bank, &mut self.rtc) {
+            (0x00..=0x03, _) => match ram_offset(ram, self.ram_bank as usize, address) {
+                Some(offset) => {
+                    ram[offset] = value;
+                    true
+                }
+                None => false,
+            },
+            (0x08..=0x0C, Some(rtc)) => {
+                rtc.write(self.ram_bank, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn save_rtc(&mut self) -> Option<Vec<u8>> {
+        self.rtc.as_mut().map(|rtc| rtc.save())
+    }
+
+    fn load_rtc(&mut self, data: &[u8]) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(data);
+        }
+    }
+}
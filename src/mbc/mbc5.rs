@@ -0,0 +1,86 @@
+/*
+MBC5 (hasta 8 MiB de ROM y 128 KiB de RAM)
+$0000-$1FFF - RAM Enable: $xA habilita la RAM
+$2000-$2FFF - 8 bits bajos del banco de ROM, a diferencia de otros MBC el banco 0
+              también se puede ver en $4000-$7FFF
+$3000-$3FFF - Bit 8 del banco de ROM
+$4000-$5FFF - RAM Bank Number (4 bits), en los cartuchos con vibración el bit 3
+              enciende el motor y solo quedan 3 bits para el banco */
+
+use crate::mbc::{ram_offset, read_rom_bank, MBC};
+
+pub struct MBC5 {
+    ram_enable: bool,
+    // 9 bits
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(has_rumble: bool) -> MBC5 {
+        MBC5 {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl MBC for MBC5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, address),
+            _ => read_rom_bank(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable = value & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | (((value & 0b1) as u16) << 8)
+            }
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = value & 0b1000 != 0;
+                    self.ram_bank = value & 0b0111;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match ram_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) => ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        match ram_offset(ram, self.ram_bank as usize, address) {
+            Some(offset) => {
+                ram[offset] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
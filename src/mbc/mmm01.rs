@@ -0,0 +1,152 @@
+/*
+MMM01, usado en cartuchos recopilatorio con un menú para elegir el juego
+Al arrancar está "sin mapear": en $0000-$7FFF se ven los últimos 32 KiB de la
+ROM, que contienen el menú. El menú configura en qué parte de la ROM está el
+juego y al activar el bit 6 de $0000-$1FFF la configuración queda bloqueada y
+el MMM01 se comporta como un MBC1 limitado a esa zona de la ROM.
+
+$0000-$1FFF - Bits 0-3: RAM Enable ($A), bit 6: mapear (bloquea la configuración)
+$2000-$3FFF - Bits 0-4: banco de ROM, bits 5-6: bits 5-6 del banco (sin mapear)
+$4000-$5FFF - Bits 0-1: banco de RAM, bits 2-3: bits altos del banco de RAM
+              (sin mapear), bits 4-5: bits 7-8 del banco de ROM (sin mapear)
+$6000-$7FFF - Bit 0: modo, bits 2-5: máscara que bloquea los bits 1-4 del banco
+              de ROM para que el juego no salga de su zona (sin mapear) */
+
+use crate::mbc::{ram_offset, read_rom_bank, MBC, ROM_BANK_SIZE};
+
+pub struct MMM01 {
+    mapped: bool,
+    ram_enable: bool,
+    // Bits 0-4
+    rom_bank_low: u8,
+    // Bits 5-6
+    rom_bank_mid: u8,
+    // Bits 7-8
+    rom_bank_high: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    // Bits 1-4 del banco de ROM que el juego no puede cambiar
+    rom_bank_mask: u8,
+    mode: bool,
+}
+
+impl Default for MMM01 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MMM01 {
+    pub fn new() -> MMM01 {
+        MMM01 {
+            mapped: false,
+            ram_enable: false,
+            rom_bank_low: 1,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            rom_bank_mask: 0,
+            mode: false,
+        }
+    }
+
+    /// Bits del banco de ROM que puede cambiar el juego
+    fn writable_bits(&self) -> u8 {
+        0b1_1111 & !(self.rom_bank_mask << 1)
+    }
+
+    /// Primer banco de la zona de ROM del juego
+    fn base_bank(&self) -> usize {
+        ((self.rom_bank_high as usize) << 7)
+            | ((self.rom_bank_mid as usize) << 5)
+            | (self.rom_bank_low & !self.writable_bits()) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.mode { self.ram_bank_low } else { 0 };
+        ((self.ram_bank_high << 2) | low) as usize
+    }
+}
+
+impl MBC for MMM01 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        if !self.mapped {
+            // El menú está en los últimos 32 KiB
+            let last_bank = (rom.len() / ROM_BANK_SIZE).max(2) - 1;
+            let bank = match address {
+                0x0000..=0x3FFF => last_bank - 1,
+                _ => last_bank,
+            };
+            return read_rom_bank(rom, bank, address);
+        }
+
+        match address {
+            0x0000..=0x3FFF => read_rom_bank(rom, self.base_bank(), address),
+            _ => {
+                let mut game_bank = self.rom_bank_low & self.writable_bits();
+                // Igual que en el MBC1 el banco 0 se convierte en 1
+                if game_bank == 0 {
+                    game_bank = 1;
+                }
+                read_rom_bank(rom, self.base_bank() | game_bank as usize, address)
+            }
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enable = value & 0x0F == 0x0A;
+                if !self.mapped && value & 0b0100_0000 != 0 {
+                    self.mapped = true;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if self.mapped {
+                    let writable = self.writable_bits();
+                    self.rom_bank_low = (self.rom_bank_low & !writable) | (value & writable);
+                } else {
+                    self.rom_bank_low = value & 0b1_1111;
+                    self.rom_bank_mid = (value >> 5) & 0b11;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = value & 0b11;
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0b11;
+                    self.rom_bank_high = (value >> 4) & 0b11;
+                }
+            }
+            _ => {
+                self.mode = value & 0b1 != 0;
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0b1111;
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match ram_offset(ram, self.ram_bank(), address) {
+            Some(offset) => ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        match ram_offset(ram, self.ram_bank(), address) {
+            Some(offset) => {
+                ram[offset] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
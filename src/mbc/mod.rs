@@ -0,0 +1,119 @@
+/*
+Memory Bank Controllers: el cartucho solo tiene 32 KiB de ROM y 8 KiB de RAM
+visibles en el mapa de memoria, los MBC permiten cambiar qué banco se ve en
+$4000-$7FFF (ROM) y en $A000-$BFFF (RAM externa) escribiendo en el área de ROM.
+
+El cartucho es el dueño de la ROM y de la RAM, cada MBC solo guarda sus
+registros y traduce las direcciones. */
+
+pub mod huc1;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod mmm01;
+pub mod rtc;
+
+use crate::cartridge::{CartridgeError, CartridgeHeader};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+pub trait MBC {
+    /// Lectura del área de ROM $0000-$7FFF
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8;
+    /// Escritura en el área de ROM $0000-$7FFF, son los registros del MBC
+    fn write_rom(&mut self, address: u16, value: u8);
+    /// Lectura de la RAM externa $A000-$BFFF
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8;
+    /// Escritura en la RAM externa $A000-$BFFF
+    /// Devuelve false si el MBC ignora la escritura (RAM deshabilitada o inexistente)
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool;
+
+    /// Estado del reloj de tiempo real para guardarlo tras la RAM, si el MBC lo tiene
+    fn save_rtc(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restaura el reloj de tiempo real guardado tras la RAM
+    fn load_rtc(&mut self, _data: &[u8]) {}
+
+    /// Tamaño de la RAM que lleva el propio MBC, sustituye al de la cabecera
+    fn internal_ram_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Devuelve true si el motor de vibración está encendido
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+/// Lee un byte de un banco de ROM, los bancos que no existen se repiten
+/// porque el cartucho no conecta las líneas de dirección altas
+pub fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+    let offset = bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+    rom[offset % rom.len()]
+}
+
+/// Devuelve la posición en la RAM externa de un byte de un banco de RAM
+pub fn ram_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    let offset = bank * RAM_BANK_SIZE + (address as usize & (RAM_BANK_SIZE - 1));
+    Some(offset % ram.len())
+}
+
+/// Cartucho sin MBC: 32 KiB de ROM y opcionalmente 8 KiB de RAM
+pub struct RomOnly;
+
+impl MBC for RomOnly {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        *rom.get(address as usize).unwrap_or(&0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match ram_offset(ram, 0, address) {
+            Some(offset) => ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match ram_offset(ram, 0, address) {
+            Some(offset) => {
+                ram[offset] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Crea el MBC que indica el byte de tipo de cartucho ($0147)
+pub fn create_mbc(header: &CartridgeHeader, rom: &[u8]) -> Result<Box<dyn MBC>, CartridgeError> {
+    let mbc: Box<dyn MBC> = match header.cartridge_type {
+        0x00 | 0x08 | 0x09 => Box::new(RomOnly),
+        0x01..=0x03 => Box::new(mbc1::MBC1::new(rom)),
+        0x05 | 0x06 => Box::new(mbc2::MBC2::new()),
+        0x0B..=0x0D => Box::new(mmm01::MMM01::new()),
+        0x0F | 0x10 => Box::new(mbc3::MBC3::new(true)),
+        0x11..=0x13 => Box::new(mbc3::MBC3::new(false)),
+        0x19..=0x1B => Box::new(mbc5::MBC5::new(false)),
+        0x1C..=0x1E => Box::new(mbc5::MBC5::new(true)),
+        0xFF => Box::new(huc1::HuC1::new()),
+        cartridge_type => {
+            return Err(CartridgeError::UnsupportedMapper(
+                cartridge_type,
+                header.cartridge_type_name(),
+            ))
+        }
+    };
+    Ok(mbc)
+}
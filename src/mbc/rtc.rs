@@ -0,0 +1,274 @@
+/*
+Reloj de tiempo real del MBC3
+$08 - RTC S  - Segundos (0-59)
+$09 - RTC M  - Minutos (0-59)
+$0A - RTC H  - Horas (0-23)
+$0B - RTC DL - 8 bits bajos del contador de días
+$0C - RTC DH - Bit 0: bit 8 del contador de días
+               Bit 6: Halt (0 = reloj activo, 1 = parado)
+               Bit 7: Day Counter Carry (el contador de días se ha desbordado)
+
+El juego lee siempre una copia de los registros que se toma al escribir
+$00 y después $01 en $6000-$7FFF (latch).
+
+El reloj avanza con la hora del host, así sigue contando con el emulador
+cerrado. Se guarda al final del fichero de partida con el formato de 48 bytes
+que usan la mayoría de emuladores (todo en little endian):
+  5 x u32: registros actuales S M H DL DH
+  5 x u32: registros latched S M H DL DH
+  u64: marca de tiempo UNIX del momento en que se guardó
+Algunos emuladores guardan la marca de tiempo en un u32 (44 bytes). */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const RTC_SAVE_SIZE: usize = 48;
+
+/// Segundos desde 1970 según el reloj del host
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    // 9 bits
+    days: u16,
+    halt: bool,
+    day_carry: bool,
+    // Copia de S M H DL DH que ve el juego
+    latched: [u8; 5],
+    // La última escritura en $6000-$7FFF fue un 0
+    latch_armed: bool,
+    // Momento en que se actualizó el reloj por última vez
+    last_update: u64,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+            last_update: unix_now(),
+        }
+    }
+
+    /// Registros actuales en el orden S M H DL DH
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            (self.days & 0xFF) as u8,
+            self.get_dh(),
+        ]
+    }
+
+    fn get_dh(&self) -> u8 {
+        ((self.days >> 8) as u8 & 0b1) | ((self.halt as u8) << 6) | ((self.day_carry as u8) << 7)
+    }
+
+    fn set_dh(&mut self, value: u8) {
+        self.days = (self.days & 0xFF) | (((value & 0b1) as u16) << 8);
+        self.halt = value & 0b0100_0000 != 0;
+        self.day_carry = value & 0b1000_0000 != 0;
+    }
+
+    /// Suma los segundos transcurridos desde la última actualización
+    fn update(&mut self) {
+        let now = unix_now();
+        if !self.halt && now > self.last_update {
+            self.advance(now - self.last_update);
+        }
+        self.last_update = now;
+    }
+
+    fn advance(&mut self, elapsed_seconds: u64) {
+        // Valores fuera de rango escritos por el juego se normalizan al avanzar
+        let seconds = self.seconds as u64 % 60 + elapsed_seconds;
+        let minutes = self.minutes as u64 % 60 + seconds / 60;
+        let hours = self.hours as u64 % 24 + minutes / 60;
+        let days = self.days as u64 + hours / 24;
+
+        self.seconds = (seconds % 60) as u8;
+        self.minutes = (minutes % 60) as u8;
+        self.hours = (hours % 24) as u8;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    /// Escritura en $6000-$7FFF: escribir $00 y luego $01 copia los registros
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.update();
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    /// Lectura de un registro ($08-$0C), siempre de la copia latched
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.latched[0] & 0b0011_1111,
+            0x09 => self.latched[1] & 0b0011_1111,
+            0x0A => self.latched[2] & 0b0001_1111,
+            0x0B => self.latched[3],
+            0x0C => self.latched[4] & 0b1100_0001,
+            _ => 0xFF,
+        }
+    }
+
+    /// Escritura en un registro ($08-$0C), modifica el reloj actual
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        match register {
+            0x08 => self.seconds = value & 0b0011_1111,
+            0x09 => self.minutes = value & 0b0011_1111,
+            0x0A => self.hours = value & 0b0001_1111,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => self.set_dh(value),
+            _ => {}
+        }
+    }
+
+    /// Serializa el reloj en el formato de 48 bytes
+    pub fn save(&mut self) -> Vec<u8> {
+        self.update();
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for &register in self.registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(register as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&self.last_update.to_le_bytes());
+        data
+    }
+
+    /// Carga el reloj guardado y le suma el tiempo que ha pasado desde entonces
+    /// Acepta el formato de 48 bytes y el de 44 con marca de tiempo de 32 bits
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < 44 {
+            return;
+        }
+        let read_u32 = |i: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&data[i * 4..i * 4 + 4]);
+            u32::from_le_bytes(bytes)
+        };
+
+        self.seconds = read_u32(0) as u8;
+        self.minutes = read_u32(1) as u8;
+        self.hours = read_u32(2) as u8;
+        self.days = read_u32(3) as u16 & 0xFF;
+        self.set_dh(read_u32(4) as u8);
+        for (i, latched) in self.latched.iter_mut().enumerate() {
+            *latched = read_u32(5 + i) as u8;
+        }
+
+        self.last_update = if data.len() >= RTC_SAVE_SIZE {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[40..48]);
+            u64::from_le_bytes(bytes)
+        } else {
+            read_u32(10) as u64
+        };
+        self.update();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reloj parado (no avanza con la hora del host) con S M H D = 12 34 5 300
+    fn halted_rtc() -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0C, 0b0100_0001);
+        rtc.write(0x08, 12);
+        rtc.write(0x09, 34);
+        rtc.write(0x0A, 5);
+        rtc.write(0x0B, 44);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        rtc
+    }
+
+    #[test]
+    fn save_round_trip_48_bytes() {
+        let mut rtc = halted_rtc();
+        let data = rtc.save();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+
+        let mut loaded = Rtc::new();
+        loaded.load(&data);
+        assert_eq!(loaded.registers(), rtc.registers());
+        assert_eq!(loaded.latched, rtc.latched);
+        assert_eq!(loaded.days, 300);
+        assert_eq!(loaded.read(0x08), 12);
+        assert_eq!(loaded.read(0x0C), 0b0100_0001);
+    }
+
+    #[test]
+    fn load_44_byte_save() {
+        let mut rtc = halted_rtc();
+        let mut data = rtc.save();
+        // Marca de tiempo en un u32
+        data.truncate(44);
+
+        let mut loaded = Rtc::new();
+        loaded.load(&data);
+        assert_eq!(loaded.registers(), rtc.registers());
+        assert_eq!(loaded.latched, rtc.latched);
+        assert_eq!(loaded.last_update, rtc.last_update);
+    }
+
+    #[test]
+    fn short_save_is_ignored() {
+        let mut rtc = Rtc::new();
+        rtc.load(&[0xFF; 40]);
+        assert_eq!(rtc.registers(), [0; 5]);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let mut rtc = Rtc::new();
+        rtc.seconds = 59;
+        rtc.minutes = 59;
+        rtc.hours = 23;
+        rtc.days = 0x1FF;
+        rtc.advance(1);
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, 0b1000_0000]);
+        assert!(rtc.day_carry);
+
+        // El carry se queda puesto hasta que el juego lo borra
+        rtc.advance(24 * 60 * 60);
+        assert_eq!(rtc.days, 1);
+        assert!(rtc.day_carry);
+    }
+
+    #[test]
+    fn advance_carries_into_day_bit_8() {
+        let mut rtc = Rtc::new();
+        rtc.hours = 23;
+        rtc.days = 0xFF;
+        rtc.advance(60 * 60);
+        assert_eq!(rtc.days, 0x100);
+        assert_eq!(rtc.get_dh() & 0b1, 1);
+        assert!(!rtc.day_carry);
+    }
+}
@@ -8,17 +8,65 @@ from 0-255 at $8000-$9000. In signed mode, tiles are numbered in two's complemen
 from -127 to 128 at $87FF-$97FF. I think... lol. Generally most ppl use 0-255 tiles,
 since it's nice and easy. */
 
-use crate::ppu::PPU;
+use crate::apu::{Apu, DEFAULT_SAMPLE_RATE};
+use crate::cartridge::Cartridge;
+use crate::dma::OamDma;
+use crate::interrupt::InterruptController;
+use crate::joypad::Joypad;
+use crate::timer::Timer;
 use std::fmt;
 
+/// Bits de los registros $FF00-$FF7F que no existen y se leen a 1
+/// Los registros que no existen se leen como $FF
+#[rustfmt::skip]
+const IO_UNUSED_BITS: [u8; 0x80] = [
+    // P1    SB    SC          DIV   TIMA  TMA   TAC
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8,
+    //                                         IF
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10  NR11  NR12  NR13  NR14        NR21  NR22
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00,
+    // NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41  NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // OBP0  OBP1  WY    WX
+    0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    // BOOT
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
 pub struct MMU {
     //0x0000 to 0xFFFF
     ram: [u8; 65_536],
 
     boot_rom: [u8; 256],
+    // Cartucho insertado, sin él la ROM se lee como $FF
+    cartridge: Option<Cartridge>,
+    // Registros IF ($FF0F) e IE ($FFFF)
+    pub interrupts: InterruptController,
+    // Registros DIV, TIMA, TMA y TAC ($FF04-$FF07)
+    pub timer: Timer,
+    // Registro P1 ($FF00)
+    pub joypad: Joypad,
+    // Registros de sonido y wave RAM ($FF10-$FF3F)
+    pub apu: Apu,
+    // Transferencia a la OAM lanzada desde $FF46
+    dma: OamDma,
+    // Bloquear el acceso a VRAM y OAM según el modo de la PPU
+    access_locking: bool,
     //pub ppu: PPU,
-    pub dirty_vram_flag: bool,
-    pub dirty_viewport_flag: bool,
 }
 
 impl fmt::Debug for MMU {
@@ -36,10 +84,8 @@ impl fmt::Debug for MMU {
              $FF48 - OBP0: {:b}, \n\
              $FF49 - OBP1: {:b}, \n\
              $FF4A - WY: {:#X}, \n\
-             $FF4B - WY: {:#X}, \n\
-
-
-            BG Tile Data: {:?} modo unsigned\n
+             $FF4B - WX: {:#X}, \n\n\n\
+             BG Tile Data: {:?} modo unsigned\n
             BG Tile Map: {:?} bg tile map 0\n",
             &self.ram[0xFF40],
             &self.ram[0xFF41],
@@ -64,41 +110,232 @@ impl fmt::Debug for MMU {
     }
 }
 
+impl Default for MMU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MMU {
     pub fn new() -> MMU {
-        let mmu = MMU {
+        MMU {
             ram: [0; 65_536],
             boot_rom: *include_bytes!("../ROMS/DMG_ROM.bin"), // Lee el fichero ROM
-            dirty_vram_flag: false,
-            dirty_viewport_flag: false, //ppu: PPU::new(),
-        };
-        mmu
+            cartridge: None,
+            interrupts: InterruptController::new(),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            apu: Apu::new(DEFAULT_SAMPLE_RATE),
+            dma: OamDma::new(),
+            access_locking: true,
+            //ppu: PPU::new(),
+        }
+    }
+
+    /// Activa o desactiva el bloqueo de VRAM y OAM según el modo de la PPU
+    /// Desactivarlo sirve para depurar programas que escriben en mal momento
+    pub fn set_access_locking(&mut self, enabled: bool) {
+        self.access_locking = enabled;
+    }
+
+    /// Devuelve true si la CPU no puede acceder a la dirección:
+    /// - Durante el DMA solo puede acceder a los registros de E/S y HRAM
+    /// - La OAM no es accesible en los modos 2 y 3, la PPU la está leyendo
+    /// - La VRAM no es accesible en el modo 3
+    fn is_bus_blocked(&self, address: u16) -> bool {
+        if self.dma.is_active() && address < 0xFF00 {
+            return true;
+        }
+        // Con el LCD apagado la PPU no accede a la memoria
+        if !self.access_locking || self.ram[0xFF40] & 0b1000_0000 == 0 {
+            return false;
+        }
+        let mode = self.ram[0xFF41] & 0b11;
+        match address {
+            0x8000..=0x9FFF => mode == 3,
+            0xFE00..=0xFE9F => mode == 2 || mode == 3,
+            _ => false,
+        }
     }
 
+    /// Escritura de la CPU, cada región del mapa de memoria se comporta distinto
     pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.ram[address as usize] = value;
-        if address >= 0x8000 && address < 0xA000 {
-            self.dirty_vram_flag = true;
+        if self.is_bus_blocked(address) {
+            return;
         }
-        if address == 0xFF42 || address == 0xFF43 {
-            self.dirty_viewport_flag = true;
+        match address {
+            // ROM del cartucho: no se escribe, van a los registros del MBC
+            0x0000..=0x7FFF => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write_rom(address, value);
+                }
+            }
+            // VRAM
+            0x8000..=0x9FFF => self.ram[address as usize] = value,
+            // RAM externa del cartucho
+            0xA000..=0xBFFF => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write_ram(address, value);
+                }
+            }
+            // WRAM
+            0xC000..=0xDFFF => self.ram[address as usize] = value,
+            // Echo RAM: espejo de $C000-$DDFF
+            0xE000..=0xFDFF => self.ram[(address - 0x2000) as usize] = value,
+            // OAM
+            0xFE00..=0xFE9F => self.ram[address as usize] = value,
+            // Zona no utilizable, se ignoran las escrituras
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.write_io(address, value),
+            // HRAM
+            0xFF80..=0xFFFE => self.ram[address as usize] = value,
+            0xFFFF => self.interrupts.set_ie(value),
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF00 => self.joypad.set_p1(value, &mut self.interrupts),
+            0xFF04 => self.timer.reset_div(),
+            0xFF05 => self.timer.set_tima(value),
+            0xFF06 => self.timer.set_tma(value),
+            0xFF07 => self.timer.set_tac(value),
+            0xFF0F => self.interrupts.set_if(value),
+            0xFF10..=0xFF3F => self.apu.write(address, value),
+            // STAT: los bits 0-2 (modo y coincidencia) son de solo lectura
+            0xFF41 => {
+                let stat = &mut self.ram[0xFF41];
+                *stat = (*stat & 0b0000_0111) | (value & 0b0111_1000);
+            }
+            // LY es de solo lectura
+            0xFF44 => {}
+            0xFF46 => {
+                self.ram[0xFF46] = value;
+                self.dma.start(value);
+            }
+            // Una vez desactivada la boot ROM no se puede volver a activar
+            0xFF50 => {
+                if self.ram[0xFF50] == 0 {
+                    self.ram[0xFF50] = value;
+                }
+            }
+            _ => self.ram[address as usize] = value,
         }
     }
 
+    /// Lectura de la CPU, lo que no es accesible en ese momento se lee como $FF
     pub fn read_byte(&self, address: u16) -> u8 {
-        if address < 0x00FF && self.ram[0xFF50] == 0 {
-            self.boot_rom[address as usize]
-        } else {
-            self.ram[address as usize]
+        if self.is_bus_blocked(address) {
+            return 0xFF;
+        }
+        self.peek_byte(address)
+    }
+
+    /// Lectura sin los bloqueos del bus que sufre la CPU, la usan la PPU y el DMA
+    /// Cada región del mapa de memoria se comporta distinto
+    pub fn peek_byte(&self, address: u16) -> u8 {
+        match address {
+            // Los primeros 256 bytes son la boot ROM hasta que se escribe en $FF50
+            0x0000..=0x00FF if self.ram[0xFF50] == 0 => self.boot_rom[address as usize],
+            0x0000..=0x7FFF => match &self.cartridge {
+                Some(cartridge) => cartridge.read_rom(address),
+                None => 0xFF,
+            },
+            0x8000..=0x9FFF => self.ram[address as usize],
+            0xA000..=0xBFFF => match &self.cartridge {
+                Some(cartridge) => cartridge.read_ram(address),
+                None => 0xFF,
+            },
+            0xC000..=0xDFFF => self.ram[address as usize],
+            0xE000..=0xFDFF => self.ram[(address - 0x2000) as usize],
+            0xFE00..=0xFE9F => self.ram[address as usize],
+            // En DMG la zona no utilizable se lee como 0
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(address),
+            0xFF80..=0xFFFE => self.ram[address as usize],
+            0xFFFF => self.interrupts.get_ie(),
+        }
+    }
+
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            0xFF00 => self.joypad.get_p1(),
+            0xFF04 => self.timer.get_div(),
+            0xFF05 => self.timer.get_tima(),
+            0xFF06 => self.timer.get_tma(),
+            0xFF07 => self.timer.get_tac(),
+            0xFF0F => self.interrupts.get_if(),
+            0xFF10..=0xFF3F => self.apu.read(address) | IO_UNUSED_BITS[(address - 0xFF00) as usize],
+            // Los bits que no existen se leen a 1
+            _ => self.ram[address as usize] | IO_UNUSED_BITS[(address - 0xFF00) as usize],
+        }
+    }
+
+    /// Avanza el DMA los ciclos de reloj que ha tardado la última instrucción
+    pub fn step_dma(&mut self, cpu_clocks_passed: usize) {
+        for _ in 0..cpu_clocks_passed / 4 {
+            match self.dma.tick() {
+                Some((source, destination)) => {
+                    self.ram[destination as usize] = self.peek_byte(source);
+                }
+                None if !self.dma.is_active() => break,
+                None => {}
+            }
+        }
+    }
+
+    /// Escritura de LY por parte de la PPU
+    pub fn set_ly(&mut self, value: u8) {
+        self.ram[0xFF44] = value;
+    }
+
+    /// Escritura de STAT por parte de la PPU, incluidos los bits de solo lectura
+    pub fn set_stat(&mut self, value: u8) {
+        self.ram[0xFF41] = value & 0b0111_1111;
+    }
+
+    /// Sustituye la boot ROM de DMG incluida en el emulador
+    pub fn set_boot_rom(&mut self, boot_rom: [u8; 256]) {
+        self.boot_rom = boot_rom;
+    }
+
+    /// Inserta un cartucho
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn get_cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    /// Devuelve true si hay un cartucho con pila, solo entonces tiene
+    /// sentido guardar la partida
+    pub fn has_battery(&self) -> bool {
+        self.cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.header.has_battery())
+    }
+
+    /// Contenido de la RAM del cartucho con pila (y su reloj) para guardarlo
+    pub fn save_ram(&mut self) -> Option<Vec<u8>> {
+        match &mut self.cartridge {
+            Some(cartridge) if cartridge.header.has_battery() => Some(cartridge.save_data()),
+            _ => None,
+        }
+    }
+
+    /// Restaura la RAM del cartucho con una partida guardada con save_ram
+    pub fn load_ram(&mut self, data: &[u8]) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.load_save_data(data);
         }
     }
 
-    pub fn from_rom_file(&mut self, rom_file: &[u8]) {
-        let mut i: u16 = 0x0000;
-        for &byte in rom_file.iter() {
-            //            println!("{:#X}", i);
-            self.write_byte(i, byte);
-            i += 1
+    /// Devuelve true si la RAM del cartucho ha cambiado desde la última llamada
+    pub fn take_ram_written(&mut self) -> bool {
+        match &mut self.cartridge {
+            Some(cartridge) => cartridge.take_ram_written(),
+            None => false,
         }
     }
 }
@@ -1,7 +1,7 @@
+use crate::fifo::PixelFifo;
+use crate::interrupt::Interrupt;
 use crate::mmu::MMU;
-
-const WIDTH: usize = 256;
-const HEIGHT: usize = 256;
+use crate::sprite::{sprites_on_line, Sprite};
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
@@ -14,247 +14,561 @@ pub const DARK_GREEN: u32 = 0xFF306230;
 pub const LIGHT_GREEN: u32 = 0xFF8BAC0F;
 pub const LIGHTEST_GREEN: u32 = 0xFF9BBC0F;
 
+/// Ciclos de reloj de un frame completo (154 líneas de 456 ciclos)
+pub const FRAME_CYCLES: usize = 70_224;
+
+/// Paletas con las que mostrar los 4 tonos, del más claro al más oscuro
+pub const PALETTES: [(&str, [u32; 4]); 3] = [
+    (
+        "green",
+        [LIGHTEST_GREEN, LIGHT_GREEN, DARK_GREEN, DARKEST_GREEN],
+    ),
+    ("gray", [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000]),
+    ("pocket", [0xFFC4CFA1, 0xFF8B956D, 0xFF4D533C, 0xFF1F1F1F]),
+];
+
+/// Busca una paleta por su nombre
+pub fn palette_by_name(name: &str) -> Option<[u32; 4]> {
+    PALETTES
+        .iter()
+        .find(|(palette_name, _)| *palette_name == name)
+        .map(|&(_, colors)| colors)
+}
+
+/// Forma de dibujar el modo 3, se elige al crear la PPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    /// Dibuja cada línea entera al final del modo 3, que dura siempre 172 ciclos
+    Scanline,
+    /// Simula el pixel FIFO punto a punto, el modo 3 dura entre 172 y 289 ciclos
+    PixelFifo,
+}
+
 pub struct PPU {
     mode: u8,
     mode_clock: usize,
-    background_buffer: Vec<u32>,
+    renderer: Renderer,
+    fifo: PixelFifo,
+    // Duración del modo 3 de la línea actual, con el FIFO no se sabe hasta que termina
+    mode3_length: usize,
+    // Línea actual, LY no siempre coincide (línea 153 y LCD apagado)
+    ly: u8,
+    // El LCD estaba encendido en el paso anterior
+    lcd_on: bool,
+    // Estado de la línea de interrupción de STAT, la interrupción se solicita
+    // solo al pasar de 0 a 1 (si una fuente ya la tenía activa las demás no cuentan)
+    stat_line: bool,
+    // Contador interno de líneas de la ventana, solo avanza en las líneas en
+    // que se dibuja, así que no depende de LY ni de WY
+    window_line: u8,
+    // LY ha coincidido con WY en este frame
+    window_y_triggered: bool,
     viewport: Vec<u32>,
+    // Hay un frame completo en viewport que aún no se ha mostrado
+    frame_ready: bool,
+    // Colores ARGB de los 4 tonos
+    palette: [u32; 4],
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PPU {
     pub fn new() -> PPU {
-        let ppu = PPU {
+        PPU::with_renderer(Renderer::Scanline)
+    }
+
+    pub fn with_renderer(renderer: Renderer) -> PPU {
+        PPU {
             mode: 0,
-            background_buffer: vec![LIGHTEST_GREEN; WIDTH * HEIGHT], // Un tile = 64 pixels
             mode_clock: 0,
+            renderer,
+            fifo: PixelFifo::new(),
+            mode3_length: 172,
+            ly: 0,
+            lcd_on: false,
+            stat_line: false,
+            window_line: 0,
+            window_y_triggered: false,
             viewport: vec![LIGHTEST_GREEN; SCREEN_WIDTH * SCREEN_HEIGHT],
-        };
-
-        ppu
+            frame_ready: false,
+            palette: PALETTES[0].1,
+        }
     }
 
     /// Devuelve registro LCDC
     pub fn get_lcdc(&self, mmu: &MMU) -> u8 {
-        mmu.read_byte(0xFF40)
+        mmu.peek_byte(0xFF40)
     }
 
     /// Devuelve registro BGP     BGP Palette Data(R/W)
     /// Asigna escala de grises a los números de color de los tiles BG y de ventana
     pub fn get_bgp(&self, mmu: &MMU) -> u8 {
-        mmu.read_byte(0xFF47)
+        mmu.peek_byte(0xFF47)
     }
 
     /// Devuelve registro SCY
     pub fn get_scy(&self, mmu: &MMU) -> u8 {
-        mmu.read_byte(0xFF42)
+        mmu.peek_byte(0xFF42)
     }
 
     /// Devuelve registro SCX
     pub fn get_scx(&self, mmu: &MMU) -> u8 {
-        mmu.read_byte(0xFF43)
+        mmu.peek_byte(0xFF43)
     }
 
     pub fn get_ly(&self, mmu: &MMU) -> u8 {
-        mmu.read_byte(0xFF44)
+        mmu.peek_byte(0xFF44)
     }
 
     pub fn get_lyc(&self, mmu: &MMU) -> u8 {
-        mmu.read_byte(0xFF45)
+        mmu.peek_byte(0xFF45)
+    }
+
+    /// Devuelve registro WY, línea donde empieza la ventana
+    pub fn get_wy(&self, mmu: &MMU) -> u8 {
+        mmu.peek_byte(0xFF4A)
+    }
+
+    /// Devuelve registro WX, columna donde empieza la ventana más 7
+    pub fn get_wx(&self, mmu: &MMU) -> u8 {
+        mmu.peek_byte(0xFF4B)
+    }
+
+    /// Devuelve registro OBP0 u OBP1, paletas de los sprites
+    pub fn get_obp(&self, mmu: &MMU, palette_1: bool) -> u8 {
+        mmu.peek_byte(if palette_1 { 0xFF49 } else { 0xFF48 })
     }
 
     pub fn get_viewport(&self) -> &Vec<u32> {
         &self.viewport
     }
 
+    /// Devuelve true si se ha completado un frame desde la última llamada
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
+    }
+
     /// Comprueba si el bit de activación de lcd está activado
     pub fn is_lcd_enable(&self, mmu: &MMU) -> bool {
         (self.get_lcdc(mmu) & 0b1000_0000) != 0
     }
 
+    /// Tiles del fondo y la ventana ordenados por su número en el mapa de tiles
     pub fn get_tile_set(&self, mmu: &MMU) -> [[u8; 16]; 256] {
-        // @TODO check LCDC
+        let lcdc = self.get_lcdc(mmu);
         let mut tile_set = [[0; 16]; 256];
 
-        for i in 0..256 {
-            tile_set[i] = self.get_tile(&mmu, (0x8000 + (i * 16)) as u16);
+        for (i, tile) in tile_set.iter_mut().enumerate() {
+            *tile = self.get_tile(mmu, tile_data_address(lcdc, i as u8));
         }
         tile_set
     }
 
+    /// Mapa de tiles del fondo, LCDC bit 3: 0 -> $9800-$9BFF, 1 -> $9C00-$9FFF
     pub fn get_tile_map(&self, mmu: &MMU) -> [u8; 1_024] {
         let mut tile_map: [u8; 1024] = [0; 1_024];
+        let map_start: u16 = if self.get_lcdc(mmu) & 0b0000_1000 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
 
-        for i in 0..1_024 {
-            tile_map[i] = mmu.read_byte((0x9800 + i) as u16);
+        for (i, tile_index) in tile_map.iter_mut().enumerate() {
+            *tile_index = mmu.peek_byte(map_start + i as u16);
         }
         tile_map
     }
 
     pub fn get_tile(&self, mmu: &MMU, first_tile_byte_addr: u16) -> [u8; 16] {
         let mut tile = [0; 16];
-        for i in 0..16 {
-            tile[i] = mmu.read_byte(first_tile_byte_addr + i as u16);
+        for (i, byte) in tile.iter_mut().enumerate() {
+            *byte = mmu.peek_byte(first_tile_byte_addr + i as u16);
         }
         tile
     }
 
-    pub fn transform_background_buffer_into_screen(&mut self, mmu: &MMU) {
-        let scx = self.get_scx(mmu) as usize;
-        let scy = self.get_scy(mmu) as usize;
-        //        let scx = 0;
-        //        let scy = 70;
-
-        self.viewport = self
-            .background_buffer
-            .iter()
-            .enumerate()
-            .filter(|(m, _)| {
-                let line = m / WIDTH;
-                let column = m % WIDTH;
-                line >= scy && line < (scy + 144) && column >= scx && column < (scx + 160)
-            })
-            .map(|(_, minifb_tile)| *minifb_tile)
-            .collect();
-    }
-
-    /// Rellena el buffer gráfico de 256 X 256 pixels
-    pub fn populate_background_buffer(&mut self, mmu: &MMU) {
-        // Obtenemos el conjunto de tiles
-        let tile_set = self.get_tile_set(mmu);
-        // Obtenemos el mapa de tiles
-        let tile_map = self.get_tile_map(mmu);
-        // Rellenamos background_buffer según tile_map y lo transformamos a tile minifb
-        for (t, tile_map_item) in tile_map.iter().enumerate() {
-            let tile = tile_set[*tile_map_item as usize];
-            let minifb_tile = self.transform_tile_to_minifb_tile(mmu, tile);
-            for (i, pixel) in minifb_tile.iter().enumerate() {
-                let h_offset = (i % 8) + ((t % 32) * 8);
-                let v_offset = ((i / 8) + (t / 32) * 8) * WIDTH;
-                self.background_buffer[h_offset + v_offset] = *pixel;
-            }
-        }
+    /// Primera fase pares de bits a paleta de background
+    pub fn transform_pair_into_bgp_palette(&self, mmu: &MMU, pixel_pair: u8) -> u8 {
+        //println!("bgp_palette = {:b}", self.get_bgp(mmu));
+        apply_palette(self.get_bgp(mmu), pixel_pair)
     }
 
-    pub fn get_background_buffer(&self) -> &Vec<u32> {
-        &self.background_buffer
+    pub fn get_palette(&self) -> [u32; 4] {
+        self.palette
     }
 
-    /// Primera fase pares de bits a paleta de background
-    pub fn transform_pair_into_bgp_palette(&self, mmu: &MMU, pixel_pair: u8) -> u8 {
-        //println!("bgp_palette = {:b}", self.get_bgp(&mmu));
-        let bgp_palette = self.get_bgp(&mmu);
-        match pixel_pair {
-            0b00 => bgp_palette & 0b0000_0011,
-            0b01 => (bgp_palette & 0b0000_1100) >> 2,
-            0b10 => (bgp_palette & 0b0011_0000) >> 4,
-            0b11 => (bgp_palette & 0b1100_0000) >> 4, // TODO: ERROR es 6
-
-            _ => bgp_palette & 0b0000_0011,
+    /// Cambia los colores de los 4 tonos, del más claro al más oscuro
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        // La pantalla vacía del arranque también cambia de color
+        for pixel in self.viewport.iter_mut() {
+            if let Some(shade) = self.palette.iter().position(|color| color == pixel) {
+                *pixel = palette[shade];
+            }
         }
+        self.palette = palette;
     }
 
     /// Segunda fase paleta de background a color para que lo entienda minifb
     pub fn transform_from_bgp_to_minifb_color(&self, bgp_palette: u8) -> u32 {
-        match bgp_palette {
-            0b00 => LIGHTEST_GREEN,
-            0b01 => LIGHT_GREEN,
-            0b10 => DARK_GREEN,
-            0b11 => DARKEST_GREEN,
-
-            _ => LIGHTEST_GREEN,
-        }
+        self.palette[(bgp_palette & 0b11) as usize]
     }
 
     /// Convierte tile en un arreglo de bits ARGB para que lo entienda minifb
     pub fn transform_tile_to_minifb_tile(&self, mmu: &MMU, tile: [u8; 16]) -> Vec<u32> {
-        let mut minifb_tile = vec![0; 64];
-        for i in (0..tile.len()).step_by(2) {
-            let pixel_part_1 = tile[i];
-            let pixel_part_2 = tile[i + 1];
-            //            println!("first byte line: {:08b}", tile[i]);
-            //            println!("second byte line: {:08b}", tile[i + 1]);
-            for j in 0..8 {
-                let bit_part_1 = pixel_part_1 & (1 << j) != 0;
-                let bit_part_2 = pixel_part_2 & (1 << j) != 0;
-
-                let pair = ((bit_part_1 as u8) << 1) | (bit_part_2 as u8);
-                //println!("pair {:b}", pair);
-
+        self.transform_tile_to_color_numbers(tile)
+            .iter()
+            .map(|pair| {
                 // Transforma este par en una paleta BGP
                 // 76     54     32     10        <-(bits en bgp_palette)
                 // color3 color2 color1 color 0
-                let bgp_palette = self.transform_pair_into_bgp_palette(&mmu, pair);
-                //println!("bgp_palette {:b}", bgp_palette);
+                let bgp_palette = self.transform_pair_into_bgp_palette(mmu, *pair);
                 // Transforma en color MINIFB
-                let minifb = self.transform_from_bgp_to_minifb_color(bgp_palette);
+                self.transform_from_bgp_to_minifb_color(bgp_palette)
+            })
+            .collect()
+    }
 
-                //minifb_tile[i / 2][7 - j] = minifb;
-                minifb_tile[(i / 2 * 8) + (7 - j) as usize] = minifb;
+    /// Convierte tile en los números de color (0-3) de sus 64 pixels
+    pub fn transform_tile_to_color_numbers(&self, tile: [u8; 16]) -> [u8; 64] {
+        let mut color_tile = [0; 64];
+        for i in (0..tile.len()).step_by(2) {
+            for j in 0..8 {
+                color_tile[(i / 2 * 8) + (7 - j)] = tile_pixel(tile[i], tile[i + 1], j as u8);
             }
         }
+        color_tile
+    }
 
-        minifb_tile
+    /// Dibuja la línea ly con los registros que hay en ese momento
+    /// Se llama al terminar el modo 3 de cada línea, así los cambios de
+    /// SCX, SCY, paletas, etc. a mitad de frame afectan solo a las líneas siguientes
+    fn render_line(&mut self, mmu: &MMU, ly: u8) {
+        let lcdc = self.get_lcdc(mmu);
+        // Números de color del fondo y la ventana, para la prioridad de los sprites
+        let mut line_colors = [0; SCREEN_WIDTH];
+
+        // LCDC bit 0: en DMG apaga el fondo y la ventana, que se ven en blanco
+        if lcdc & 0b0000_0001 != 0 {
+            self.render_background_line(mmu, ly, &mut line_colors);
+            self.render_window_line(mmu, &mut line_colors);
+        }
+
+        let bgp = self.get_bgp(mmu);
+        let line_start = ly as usize * SCREEN_WIDTH;
+        for (x, color) in line_colors.iter().enumerate() {
+            self.viewport[line_start + x] =
+                self.transform_from_bgp_to_minifb_color(apply_palette(bgp, *color));
+        }
+
+        // LCDC bit 1: sprites activados
+        if lcdc & 0b0000_0010 != 0 {
+            self.render_sprites_line(mmu, ly, &line_colors);
+        }
     }
 
-    pub fn step(&mut self, cpu_clocks_passed: usize, mmu: &mut MMU) {
-        // Comprueba si el LCD está habilitado
-        let lcdc: u8 = mmu.read_byte(0xFF40);
-        let is_lcd_enable = (lcdc & 0b1000_0000) != 0;
-
-        // Si LCD está habilitado
-        if is_lcd_enable {
-            // incrementar el reloj interno
-            self.mode_clock += cpu_clocks_passed;
-            // probar en que modo estamos
-            let mut ly: u8 = mmu.read_byte(0xFF44);
-            if self.mode_clock > 456 && self.mode != 1 {
-                // Esto ocurre en HBLANK
-                ly = ly.wrapping_add(1);
-                mmu.write_byte(0xFF44, ly);
-                if ly <= 144 {
-                    self.mode_clock = 0;
+    /// Fondo de la línea ly, el mapa de 256x256 pixels se repite en los dos ejes
+    fn render_background_line(&self, mmu: &MMU, ly: u8, line_colors: &mut [u8; SCREEN_WIDTH]) {
+        let lcdc = self.get_lcdc(mmu);
+        let scx = self.get_scx(mmu);
+        // LCDC bit 3: mapa de tiles del fondo
+        let tile_map: u16 = if lcdc & 0b0000_1000 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let y = ly.wrapping_add(self.get_scy(mmu)) as u16;
+
+        for (x, color) in line_colors.iter_mut().enumerate() {
+            let column = (x as u8).wrapping_add(scx) as u16;
+            let tile_index = mmu.peek_byte(tile_map + (y / 8) * 32 + column / 8);
+            let address = tile_data_address(lcdc, tile_index) + (y % 8) * 2;
+            let bit = 7 - (column % 8) as u8;
+            *color = tile_pixel(mmu.peek_byte(address), mmu.peek_byte(address + 1), bit);
+        }
+    }
+
+    /// Ventana de la línea actual, encima del fondo
+    fn render_window_line(&mut self, mmu: &MMU, line_colors: &mut [u8; SCREEN_WIDTH]) {
+        // LCDC bit 5: ventana activada
+        let lcdc = self.get_lcdc(mmu);
+        let wx = self.get_wx(mmu);
+        if lcdc & 0b0010_0000 == 0 || !self.window_y_triggered || wx > 166 {
+            return;
+        }
+
+        // LCDC bit 6: mapa de tiles de la ventana
+        let tile_map: u16 = if lcdc & 0b0100_0000 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let row = self.window_line as u16;
+        // WX = 7 es la columna 0 de la pantalla, con WX < 7 la ventana empieza recortada
+        let start_x = wx as i16 - 7;
+        for x in start_x.max(0)..SCREEN_WIDTH as i16 {
+            let column = (x - start_x) as u16;
+            let tile_index = mmu.peek_byte(tile_map + (row / 8) * 32 + column / 8);
+            let address = tile_data_address(lcdc, tile_index) + (row % 8) * 2;
+            let bit = 7 - (column % 8) as u8;
+            line_colors[x as usize] =
+                tile_pixel(mmu.peek_byte(address), mmu.peek_byte(address + 1), bit);
+        }
+        self.window_line = self.window_line.wrapping_add(1);
+    }
+
+    /// Dibuja los sprites de la línea ly
+    fn render_sprites_line(&mut self, mmu: &MMU, ly: u8, line_colors: &[u8; SCREEN_WIDTH]) {
+        // LCDC bit 2: sprites de 8x16
+        let height = if self.get_lcdc(mmu) & 0b0000_0100 != 0 {
+            16
+        } else {
+            8
+        };
+        let sprites = sprites_on_line(mmu, ly, height);
+        let line_start = ly as usize * SCREEN_WIDTH;
+
+        for (x, bg_color) in line_colors.iter().enumerate() {
+            // El primer sprite con un pixel no transparente es el que se ve
+            let pixel = sprites.iter().find_map(|sprite| {
+                let color = self.sprite_pixel(mmu, sprite, ly, height, x as i16)?;
+                Some((sprite, color))
+            });
+            if let Some((sprite, color)) = pixel {
+                if sprite.behind_bg && *bg_color != 0 {
+                    continue;
                 }
+                let palette = self.get_obp(mmu, sprite.palette_1);
+                self.viewport[line_start + x] =
+                    self.transform_from_bgp_to_minifb_color(apply_palette(palette, color));
             }
+        }
+    }
 
-            match self.mode_clock {
-                t if t <= 80 => self.mode = 2,
-                t if t <= 252 => self.mode = 3,  // Acumulado 80 + 172
-                t if t <= 456 => self.mode = 0,  // Acumulado 80 + 172 + 204
-                t if t <= 4560 => self.mode = 1, // Cada 10 lineas
-                t if t > 4560 => {
-                    self.mode = 2;
-                    self.mode_clock = 0;
-                    if ly > 154 {
-                        // Es correcto, un frame entero cada 154 scanlines
-                        mmu.write_byte(0xFF44, 0);
-                    }
-                }
-                _ => panic!("mode_clock no manejado!"),
+    /// Número de color del sprite en la columna x de la pantalla
+    /// None si el sprite no ocupa esa columna o el pixel es transparente (color 0)
+    fn sprite_pixel(&self, mmu: &MMU, sprite: &Sprite, ly: u8, height: u8, x: i16) -> Option<u8> {
+        if x < sprite.x || x >= sprite.x + 8 {
+            return None;
+        }
+        let column = (x - sprite.x) as u8;
+        let bit = if sprite.x_flip { column } else { 7 - column };
+        let address = sprite.row_address(ly, height);
+        let color = tile_pixel(mmu.peek_byte(address), mmu.peek_byte(address + 1), bit);
+        if color == 0 {
+            None
+        } else {
+            Some(color)
+        }
+    }
+
+    /// Duración en ciclos del modo actual
+    fn mode_length(&self) -> usize {
+        match self.mode {
+            2 => 80,
+            3 => self.mode3_length,
+            // El HBLANK completa los 456 ciclos de la línea
+            0 => 376 - self.mode3_length,
+            _ => 456,
+        }
+    }
+
+    /// Ciclos que faltan hasta el siguiente cambio de modo
+    /// Con el LCD apagado no hay eventos, devuelve lo que dura una línea
+    pub fn cycles_until_next_event(&self, mmu: &MMU) -> usize {
+        if !self.is_lcd_enable(mmu) {
+            return 456;
+        }
+        if self.mode == 1 && self.ly == 153 && self.mode_clock < 4 {
+            // LY pasa a valer 0
+            return 4 - self.mode_clock;
+        }
+        if self.mode == 3 && self.mode3_length == usize::MAX {
+            // El modo 3 con el FIFO dura como mínimo 172 ciclos
+            return 172usize.saturating_sub(self.mode_clock).max(1);
+        }
+        self.mode_length().saturating_sub(self.mode_clock).max(1)
+    }
+
+    /// Avanza el pixel FIFO hasta los ciclos que lleva el modo 3
+    /// Cuando termina la línea ya se sabe cuánto dura el modo 3
+    fn run_fifo(&mut self, mmu: &MMU) {
+        while !self.fifo.is_done() && self.fifo.dots() < self.mode_clock {
+            self.fifo.tick(mmu);
+        }
+        if self.fifo.is_done() {
+            self.mode3_length = self.fifo.dots();
+        }
+    }
+
+    /// Copia la línea que ha dibujado el FIFO en la pantalla
+    fn finish_fifo_line(&mut self, ly: u8) {
+        let line_start = ly as usize * SCREEN_WIDTH;
+        for (x, color) in self.fifo.get_line().iter().enumerate() {
+            self.viewport[line_start + x] = self.transform_from_bgp_to_minifb_color(*color);
+        }
+        if self.fifo.used_window() {
+            self.window_line = self.window_line.wrapping_add(1);
+        }
+    }
+
+    pub fn step(&mut self, cpu_clocks_passed: usize, mmu: &mut MMU) {
+        if !self.is_lcd_enable(mmu) {
+            if self.lcd_on {
+                self.turn_off(mmu);
             }
+            return;
+        }
+        if !self.lcd_on {
+            // Al encender el LCD se empieza desde la línea 0
+            self.lcd_on = true;
+            self.mode = 2;
+            self.mode_clock = 0;
+            self.ly = 0;
+            self.mode3_length = 172;
+            self.window_line = 0;
+            self.window_y_triggered = self.get_wy(mmu) == 0;
+            self.update_stat(mmu);
+        }
 
-            // cambiar los registros apropiados de la PPU (LY, LYC, STAT)
-            // @TODO Check LYC behavior
-            let lyc = mmu.read_byte(0xFF45);
-            let stat_bit_0_to_2: u8 = match ly == lyc {
-                true => 0b100 | self.mode, // bit 3 es flag de coincidencia (ly == lyc)
-                false => self.mode,
-            } as u8;
-            let mut current_stat = mmu.read_byte(0xFF41);
-            current_stat = current_stat & 0b11111000;
-            current_stat = current_stat | stat_bit_0_to_2;
-            // set registro STAT
-            mmu.write_byte(0xFF41, current_stat);
-            if self.mode == 2 {
-                if mmu.dirty_vram_flag {
-                    self.populate_background_buffer(mmu);
-                    self.transform_background_buffer_into_screen(mmu);
-                    mmu.dirty_vram_flag = false;
+        // incrementar el reloj interno
+        self.mode_clock += cpu_clocks_passed;
+
+        // Cada scanline dura 456 ciclos: modo 2 (80), modo 3 (172) y modo 0 (204)
+        // Las líneas 144 a 153 son VBLANK (modo 1)
+        loop {
+            if self.mode == 3 && self.renderer == Renderer::PixelFifo {
+                self.run_fifo(mmu);
+            }
+            let mode_length = self.mode_length();
+            if self.mode_clock < mode_length {
+                break;
+            }
+            self.mode_clock -= mode_length;
+
+            match self.mode {
+                2 => {
+                    self.mode = 3;
+                    if self.renderer == Renderer::PixelFifo {
+                        self.fifo.start_line(
+                            mmu,
+                            self.ly,
+                            self.window_y_triggered,
+                            self.window_line,
+                        );
+                        // Aún no se sabe cuánto va a durar
+                        self.mode3_length = usize::MAX;
+                    }
                 }
-                if mmu.dirty_viewport_flag {
-                    self.transform_background_buffer_into_screen(mmu);
-                    mmu.dirty_viewport_flag = false;
+                3 => {
+                    self.mode = 0;
+                    match self.renderer {
+                        Renderer::Scanline => self.render_line(mmu, self.ly),
+                        Renderer::PixelFifo => self.finish_fifo_line(self.ly),
+                    }
+                }
+                0 => {
+                    self.ly += 1;
+                    if self.ly == 144 {
+                        self.mode = 1;
+                        self.frame_ready = true;
+                        mmu.interrupts.request(Interrupt::VBlank);
+                    } else {
+                        self.mode = 2;
+                    }
+                }
+                _ => {
+                    self.ly += 1;
+                    if self.ly > 153 {
+                        // Es correcto, un frame entero cada 154 scanlines
+                        self.ly = 0;
+                        self.mode = 2;
+                        // La ventana empieza de nuevo en cada frame
+                        self.window_line = 0;
+                        self.window_y_triggered = false;
+                    }
                 }
             }
+            // La ventana aparece desde la primera línea en que LY == WY
+            if self.mode == 2 && self.ly == self.get_wy(mmu) {
+                self.window_y_triggered = true;
+            }
+            self.update_stat(mmu);
         }
+        // LY cambia a mitad de la línea 153
+        self.update_stat(mmu);
     }
+
+    /// Valor de LY: en la línea 153 pasa a valer 0 a los 4 ciclos
+    fn visible_ly(&self) -> u8 {
+        if self.ly == 153 && self.mode_clock >= 4 {
+            0
+        } else {
+            self.ly
+        }
+    }
+
+    /// Actualiza LY y STAT y solicita la interrupción de STAT si se activa
+    fn update_stat(&mut self, mmu: &mut MMU) {
+        let ly = self.visible_ly();
+        mmu.set_ly(ly);
+
+        let stat = mmu.peek_byte(0xFF41);
+        let coincidence = ly == self.get_lyc(mmu);
+        // Bits 0-1: modo, bit 2: coincidencia LY == LYC, el resto no se toca
+        let mut new_stat = (stat & 0b0111_1000) | self.mode;
+        if coincidence {
+            new_stat |= 0b0000_0100;
+        }
+        mmu.set_stat(new_stat);
+
+        // Bit 3: HBLANK, bit 4: VBLANK, bit 5: OAM, bit 6: LY == LYC
+        let stat_line = (stat & 0b0000_1000 != 0 && self.mode == 0)
+            || (stat & 0b0001_0000 != 0 && self.mode == 1)
+            || (stat & 0b0010_0000 != 0 && self.mode == 2)
+            || (stat & 0b0100_0000 != 0 && coincidence);
+        if stat_line && !self.stat_line {
+            mmu.interrupts.request(Interrupt::Stat);
+        }
+        self.stat_line = stat_line;
+    }
+
+    /// Con el LCD apagado LY vale 0 y STAT indica modo 0
+    fn turn_off(&mut self, mmu: &mut MMU) {
+        self.lcd_on = false;
+        self.mode = 0;
+        self.mode_clock = 0;
+        self.ly = 0;
+        self.stat_line = false;
+        mmu.set_ly(0);
+        let stat = mmu.peek_byte(0xFF41);
+        mmu.set_stat(stat & 0b0111_1100);
+    }
+}
+
+/// Dirección del primer byte de un tile del fondo o la ventana
+/// LCDC bit 4: 1 -> $8000-$8FFF con números de tile sin signo
+///             0 -> $8800-$97FF con números con signo, el tile 0 está en $9000
+pub fn tile_data_address(lcdc: u8, tile_index: u8) -> u16 {
+    if lcdc & 0b0001_0000 != 0 {
+        0x8000 + tile_index as u16 * 16
+    } else {
+        (0x9000 + tile_index as i8 as i32 * 16) as u16
+    }
+}
+
+/// Número de color (0-3) del bit de una fila de tile
+/// El primer byte de la fila tiene el bit bajo del color y el segundo el alto
+pub fn tile_pixel(low: u8, high: u8, bit: u8) -> u8 {
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
+
+/// Aplica una paleta (BGP, OBP0 u OBP1) a un número de color
+/// 76     54     32     10        <-(bits en la paleta)
+/// color3 color2 color1 color 0
+pub fn apply_palette(palette: u8, color: u8) -> u8 {
+    (palette >> ((color & 0b11) * 2)) & 0b11
 }
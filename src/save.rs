@@ -0,0 +1,100 @@
+/*
+Partidas guardadas de los cartuchos con pila (.sav)
+
+El fichero contiene la RAM externa tal cual, seguida del estado del reloj si
+el cartucho es un MBC3 con RTC, el mismo formato que usan otros emuladores.
+
+Se carga al arrancar y se escribe al salir y unos segundos después de la
+última escritura del juego en la RAM, así no se escribe a disco en cada byte.
+La escritura es atómica: primero se escribe un fichero temporal y después se
+renombra encima del anterior, si el emulador se cierra a medias la partida
+anterior sigue intacta. */
+
+use crate::mmu::MMU;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Tiempo sin escrituras en la RAM que se espera antes de guardar
+pub const SAVE_DELAY: Duration = Duration::from_secs(3);
+
+pub struct SaveFile {
+    path: PathBuf,
+    // Momento de la última escritura que aún no se ha guardado
+    last_write: Option<Instant>,
+}
+
+impl SaveFile {
+    /// Fichero de partida de una ROM: el mismo nombre con extensión .sav
+    pub fn for_rom(rom_path: &Path) -> SaveFile {
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            last_write: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Carga la partida en el cartucho si tiene pila y existe el fichero
+    /// Devuelve true si se ha cargado
+    pub fn load(&self, mmu: &mut MMU) -> io::Result<bool> {
+        if !mmu.has_battery() {
+            return Ok(false);
+        }
+        match fs::read(&self.path) {
+            Ok(data) => {
+                mmu.load_ram(&data);
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Se llama periódicamente, guarda cuando han pasado SAVE_DELAY desde la
+    /// última escritura en la RAM
+    pub fn update(&mut self, mmu: &mut MMU) -> io::Result<()> {
+        if mmu.take_ram_written() {
+            self.last_write = Some(Instant::now());
+        }
+        match self.last_write {
+            Some(last_write) if last_write.elapsed() >= SAVE_DELAY => self.flush(mmu),
+            _ => Ok(()),
+        }
+    }
+
+    /// Guarda la partida si hay cambios pendientes, al salir del emulador
+    pub fn flush_if_dirty(&mut self, mmu: &mut MMU) -> io::Result<()> {
+        if mmu.take_ram_written() || self.last_write.is_some() {
+            self.flush(mmu)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Escribe la partida en disco
+    pub fn flush(&mut self, mmu: &mut MMU) -> io::Result<()> {
+        self.last_write = None;
+        match mmu.save_ram() {
+            Some(data) => write_atomic(&self.path, &data),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Escribe en un fichero temporal junto al destino y lo renombra encima
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    // Los datos tienen que estar en disco antes de sustituir el fichero
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,56 @@
+/*
+Capturas de pantalla en formato BMP de 24 bits, sin dependencias externas
+
+Cabecera de fichero (14 bytes): "BM", tamaño del fichero, 0, posición de los píxeles
+Cabecera BITMAPINFOHEADER (40 bytes): 40, ancho, alto, 1 plano, 24 bits,
+sin compresión, tamaño de los píxeles, resolución (2835 px/m = 72 ppp), 0, 0
+Píxeles: las filas van de abajo a arriba, cada píxel en orden B, G, R y cada
+fila se rellena con ceros hasta un múltiplo de 4 bytes. */
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+const PIXELS_PER_METER: u32 = 2835;
+
+/// Guarda los píxeles ARGB de la pantalla (los de minifb) en un fichero BMP
+pub fn save_bmp<P: AsRef<Path>>(
+    path: P,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    let row_size = (width * 3 + 3) & !3;
+    let pixels_size = (row_size * height) as u32;
+    let pixels_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"BM")?;
+    writer.write_all(&(pixels_offset + pixels_size).to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&pixels_offset.to_le_bytes())?;
+
+    writer.write_all(&INFO_HEADER_SIZE.to_le_bytes())?;
+    writer.write_all(&(width as i32).to_le_bytes())?;
+    writer.write_all(&(height as i32).to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&24u16.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&pixels_size.to_le_bytes())?;
+    writer.write_all(&PIXELS_PER_METER.to_le_bytes())?;
+    writer.write_all(&PIXELS_PER_METER.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+
+    let mut row = vec![0u8; row_size];
+    for y in (0..height).rev() {
+        for (x, &pixel) in pixels[y * width..(y + 1) * width].iter().enumerate() {
+            let [blue, green, red, _alpha] = pixel.to_le_bytes();
+            row[x * 3..x * 3 + 3].copy_from_slice(&[blue, green, red]);
+        }
+        writer.write_all(&row)?;
+    }
+    writer.flush()
+}
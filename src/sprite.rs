@@ -0,0 +1,87 @@
+/*
+$FE00-$FE9F - OAM (Object Attribute Memory)
+40 sprites de 4 bytes cada uno:
+  Byte 0 - Posición Y + 16 (Y = 0 oculta el sprite)
+  Byte 1 - Posición X + 8  (X = 0 oculta el sprite)
+  Byte 2 - Número de tile, siempre de $8000-$8FFF
+           En modo 8x16 se ignora el bit 0: arriba tile & $FE, abajo tile | $01
+  Byte 3 - Atributos
+    Bit 7 - OBJ-to-BG Priority (0 = sprite encima del BG, 1 = detrás de los colores 1-3 del BG)
+    Bit 6 - Y flip
+    Bit 5 - X flip
+    Bit 4 - Paleta (0 = OBP0, 1 = OBP1)
+
+Como mucho se dibujan 10 sprites por línea: los 10 primeros de la OAM que
+ocupan la línea, aunque estén fuera de la pantalla en horizontal.
+En DMG, cuando se solapan, tiene prioridad el de menor X y si coinciden el
+que está antes en la OAM. */
+
+use crate::mmu::MMU;
+
+pub const OAM_START: u16 = 0xFE00;
+pub const OAM_SPRITES: usize = 40;
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    // Posición en la pantalla, ya restados los desplazamientos de 16 y 8
+    pub y: i16,
+    pub x: i16,
+    pub tile: u8,
+    pub behind_bg: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    // false -> OBP0, true -> OBP1
+    pub palette_1: bool,
+    // Posición en la OAM, desempata cuando dos sprites tienen la misma X
+    pub oam_index: usize,
+}
+
+impl Sprite {
+    /// Lee el sprite número index de la OAM
+    pub fn from_oam(mmu: &MMU, index: usize) -> Sprite {
+        let address = OAM_START + (index * 4) as u16;
+        let attributes = mmu.peek_byte(address + 3);
+        Sprite {
+            y: mmu.peek_byte(address) as i16 - 16,
+            x: mmu.peek_byte(address + 1) as i16 - 8,
+            tile: mmu.peek_byte(address + 2),
+            behind_bg: attributes & 0b1000_0000 != 0,
+            y_flip: attributes & 0b0100_0000 != 0,
+            x_flip: attributes & 0b0010_0000 != 0,
+            palette_1: attributes & 0b0001_0000 != 0,
+            oam_index: index,
+        }
+    }
+
+    /// Dirección de los dos bytes de la fila del sprite que cae en la línea ly
+    pub fn row_address(&self, ly: u8, height: u8) -> u16 {
+        let mut row = (ly as i16 - self.y) as u8;
+        if self.y_flip {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 {
+            // En 8x16 el bit 0 lo decide la mitad del sprite
+            (self.tile & 0xFE) | (row >> 3)
+        } else {
+            self.tile
+        };
+        0x8000 + tile as u16 * 16 + (row as u16 & 0b111) * 2
+    }
+}
+
+/// Búsqueda en la OAM (modo 2): sprites que se dibujan en la línea ly,
+/// ordenados de mayor a menor prioridad
+pub fn sprites_on_line(mmu: &MMU, ly: u8, height: u8) -> Vec<Sprite> {
+    let mut sprites: Vec<Sprite> = (0..OAM_SPRITES)
+        .map(|index| Sprite::from_oam(mmu, index))
+        .filter(|sprite| {
+            let ly = ly as i16;
+            ly >= sprite.y && ly < sprite.y + height as i16
+        })
+        .take(MAX_SPRITES_PER_LINE)
+        .collect();
+    // sort_by_key es estable, a igual X se mantiene el orden de la OAM
+    sprites.sort_by_key(|sprite| sprite.x);
+    sprites
+}
@@ -0,0 +1,240 @@
+/*
+$FF04 - DIV  - Divider Register (R/W): byte alto de un contador interno de 16 bits
+                que avanza con cada ciclo de reloj, al escribirlo se pone a 0
+$FF05 - TIMA - Timer counter (R/W): se incrementa a la frecuencia de TAC y al
+                desbordarse se recarga con TMA y solicita la interrupción de Timer
+$FF06 - TMA  - Timer Modulo (R/W)
+$FF07 - TAC  - Timer Control (R/W)
+  Bit 2    - Timer Enable
+  Bits 1-0 - Frecuencia: 00: 4096 Hz, 01: 262144 Hz, 10: 65536 Hz, 11: 16384 Hz
+
+TIMA no cuenta ciclos sino flancos de bajada de un bit del divisor interno
+(bit 9, 3, 5 o 7 según TAC) combinado con el bit de enable, por eso escribir
+en DIV o en TAC puede incrementar TIMA. */
+
+use crate::interrupt::{Interrupt, InterruptController};
+
+pub struct Timer {
+    // Divisor interno de 16 bits, DIV es el byte alto
+    divider: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA se ha desbordado y se recargará con TMA en el siguiente ciclo de máquina
+    reload_pending: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            divider: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_pending: false,
+        }
+    }
+
+    pub fn get_div(&self) -> u8 {
+        (self.divider >> 8) as u8
+    }
+
+    /// Divisor interno completo, la APU usa su bit 12 para el frame sequencer
+    pub fn get_divider(&self) -> u16 {
+        self.divider
+    }
+
+    /// Cualquier escritura en DIV pone a 0 todo el divisor interno
+    pub fn reset_div(&mut self) {
+        let old_input = self.timer_input();
+        self.divider = 0;
+        self.detect_falling_edge(old_input);
+    }
+
+    pub fn get_tima(&self) -> u8 {
+        self.tima
+    }
+
+    /// Escribir TIMA mientras está pendiente la recarga la cancela
+    pub fn set_tima(&mut self, value: u8) {
+        self.reload_pending = false;
+        self.tima = value;
+    }
+
+    pub fn get_tma(&self) -> u8 {
+        self.tma
+    }
+
+    pub fn set_tma(&mut self, value: u8) {
+        self.tma = value;
+    }
+
+    /// Lectura de TAC, los bits 3-7 se leen a 1
+    pub fn get_tac(&self) -> u8 {
+        self.tac | 0b1111_1000
+    }
+
+    pub fn set_tac(&mut self, value: u8) {
+        let old_input = self.timer_input();
+        self.tac = value & 0b0000_0111;
+        self.detect_falling_edge(old_input);
+    }
+
+    /// Bit del divisor interno que vigila TIMA según la frecuencia de TAC
+    fn divider_bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7,    // 16384 Hz
+        }
+    }
+
+    /// Señal que incrementa TIMA en su flanco de bajada
+    fn timer_input(&self) -> bool {
+        let enabled = self.tac & 0b100 != 0;
+        enabled && self.divider & (1 << self.divider_bit()) != 0
+    }
+
+    fn detect_falling_edge(&mut self, old_input: bool) {
+        if old_input && !self.timer_input() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (new_tima, overflow) = self.tima.overflowing_add(1);
+        // Al desbordarse TIMA vale 0 durante un ciclo de máquina antes de recargarse
+        self.tima = new_tima;
+        if overflow {
+            self.reload_pending = true;
+        }
+    }
+
+    /// Avanza el timer un ciclo de máquina (4 ciclos de reloj)
+    fn tick(&mut self, interrupts: &mut InterruptController) {
+        if self.reload_pending {
+            self.reload_pending = false;
+            self.tima = self.tma;
+            interrupts.request(Interrupt::Timer);
+        }
+
+        let old_input = self.timer_input();
+        self.divider = self.divider.wrapping_add(4);
+        self.detect_falling_edge(old_input);
+    }
+
+    /// Avanza el timer los ciclos de reloj que ha tardado la última instrucción
+    pub fn step(&mut self, cpu_clocks_passed: usize, interrupts: &mut InterruptController) {
+        for _ in 0..cpu_clocks_passed / 4 {
+            self.tick(interrupts);
+        }
+    }
+
+    /// Ciclos que faltan hasta que TIMA se desborde
+    pub fn cycles_until_next_event(&self) -> usize {
+        if self.reload_pending {
+            return 4;
+        }
+        if self.tac & 0b100 == 0 {
+            return usize::MAX;
+        }
+        let period = 1usize << (self.divider_bit() + 1);
+        let until_next_increment = period - (self.divider as usize & (period - 1));
+        let increments_until_overflow = 0x100 - self.tima as usize;
+        until_next_increment + (increments_until_overflow - 1) * period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMER_IF: u8 = 0b0000_0100;
+
+    /// Timer a 262144 Hz: TIMA se incrementa cada 16 ciclos de reloj
+    fn fast_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.set_tac(0b101);
+        timer
+    }
+
+    #[test]
+    fn tima_overflow_reloads_one_cycle_later() {
+        let mut timer = fast_timer();
+        let mut interrupts = InterruptController::new();
+        timer.set_tima(0xFF);
+        timer.set_tma(0x42);
+
+        timer.step(16, &mut interrupts);
+        // Durante un ciclo de máquina TIMA vale 0 y no hay interrupción
+        assert_eq!(timer.get_tima(), 0x00);
+        assert_eq!(interrupts.get_if() & TIMER_IF, 0);
+
+        timer.step(4, &mut interrupts);
+        assert_eq!(timer.get_tima(), 0x42);
+        assert_eq!(interrupts.get_if() & TIMER_IF, TIMER_IF);
+    }
+
+    #[test]
+    fn tima_write_cancels_pending_reload() {
+        let mut timer = fast_timer();
+        let mut interrupts = InterruptController::new();
+        timer.set_tima(0xFF);
+        timer.set_tma(0x42);
+
+        timer.step(16, &mut interrupts);
+        timer.set_tima(0x10);
+        timer.step(4, &mut interrupts);
+        assert_eq!(timer.get_tima(), 0x10);
+        assert_eq!(interrupts.get_if() & TIMER_IF, 0);
+    }
+
+    #[test]
+    fn div_write_on_high_bit_increments_tima() {
+        let mut timer = fast_timer();
+        let mut interrupts = InterruptController::new();
+
+        // Bit 3 del divisor a 1: al ponerlo a 0 hay flanco de bajada
+        timer.step(8, &mut interrupts);
+        assert_eq!(timer.get_tima(), 0);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 1);
+        assert_eq!(timer.get_div(), 0);
+
+        // Bit 3 a 0: no hay flanco
+        timer.step(4, &mut interrupts);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 1);
+    }
+
+    #[test]
+    fn disabling_timer_on_high_bit_increments_tima() {
+        let mut timer = fast_timer();
+        let mut interrupts = InterruptController::new();
+        timer.step(8, &mut interrupts);
+        timer.set_tac(0b001);
+        assert_eq!(timer.get_tima(), 1);
+    }
+
+    #[test]
+    fn cycles_until_overflow() {
+        let mut timer = fast_timer();
+        let mut interrupts = InterruptController::new();
+        timer.set_tima(0xFE);
+        let cycles = timer.cycles_until_next_event();
+        assert_eq!(cycles, 32);
+
+        timer.step(cycles - 4, &mut interrupts);
+        assert_eq!(timer.get_tima(), 0xFF);
+        timer.step(4, &mut interrupts);
+        assert_eq!(timer.get_tima(), 0x00);
+        assert_eq!(timer.cycles_until_next_event(), 4);
+    }
+}